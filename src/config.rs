@@ -1,10 +1,70 @@
-use crate::touch::TriggerCorner;
+use crate::fill::FillMode;
+use crate::gesture::parse_gesture_actions;
+use crate::mcp::McpServerConfig;
+use crate::touch::{TriggerArea, TriggerCorner};
+use crate::usage::{default_prices, ModelPrice};
 use anyhow::Result;
 use figment::{
     providers::{Env, Format, Serialized, Toml},
     Figment,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// One entry of `[[fallback_models]]`. The engine is guessed from the model
+/// name when left out, and the base URL and API key default to the usual
+/// environment variables for that engine.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FallbackModel {
+    pub engine: Option<String>,
+    pub model: String,
+    pub base_url: Option<String>,
+    pub api_key: Option<String>,
+}
+
+/// One entry of `[[zones]]`: another place to tap, set by a corner or a
+/// `[x, y, width, height]` rectangle in virtual screen pixels (768x1024), with
+/// its own prompt, model, and tools. Anything left out comes from the main settings.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TriggerZone {
+    pub name: String,
+    pub corner: Option<String>,
+    pub rect: Option<[i32; 4]>,
+    pub prompt: Option<String>,
+    /// Replaces the prompt file's list of tools
+    pub tools: Option<Vec<String>>,
+    pub engine: Option<String>,
+    pub model: Option<String>,
+    pub base_url: Option<String>,
+    pub api_key: Option<String>,
+}
+
+impl TriggerZone {
+    pub fn area(&self) -> Result<TriggerArea> {
+        match (&self.corner, self.rect) {
+            (Some(corner), None) => Ok(TriggerArea::Corner(TriggerCorner::from_string(corner)?)),
+            (None, Some([x, y, width, height])) if width > 0 && height > 0 => Ok(TriggerArea::Rect { x, y, width, height }),
+            (None, Some(_)) => Err(anyhow::anyhow!("Zone {} needs a rect with a positive width and height", self.name)),
+            _ => Err(anyhow::anyhow!("Zone {} needs either a corner or a rect, not both", self.name)),
+        }
+    }
+
+    /// The main configuration with this zone's prompt and model swapped in. A
+    /// zone with its own model doesn't inherit the main engine, base URL, or API key.
+    pub fn apply(&self, config: &Config) -> Config {
+        let mut config = config.clone();
+        if let Some(prompt) = &self.prompt {
+            config.prompt = prompt.clone();
+        }
+        if let Some(model) = &self.model {
+            config.model = model.clone();
+            config.engine = self.engine.clone();
+            config.engine_base_url = self.base_url.clone();
+            config.engine_api_key = self.api_key.clone();
+        }
+        config
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
@@ -18,20 +78,55 @@ pub struct Config {
     pub no_draw: bool,
     pub no_svg: bool,
     pub no_keyboard: bool,
+    pub svg_mode: String,
+    pub fill_mode: String,
+    pub fill_spacing: u32,
     pub no_draw_progress: bool,
     pub input_png: Option<String>,
     pub output_file: Option<String>,
     pub model_output_file: Option<String>,
+    pub model_output_include_images: bool,
     pub save_screenshot: Option<String>,
     pub save_bitmap: Option<String>,
     pub no_loop: bool,
     pub no_trigger: bool,
     pub apply_segmentation: bool,
+    pub crop_new_ink: bool,
+    pub conversation: bool,
+    pub stream: bool,
+    pub max_tool_steps: u32,
+    pub max_history_images: usize,
+    pub max_retries: u32,
     pub web_search: bool,
     pub thinking: bool,
     pub thinking_tokens: u32,
+    pub reasoning_effort: Option<String>,
+    pub builtin_tools: Option<String>,
+    pub no_stateful: bool,
+    pub tool_mode: String,
+    /// MCP servers whose tools are offered to the model; only settable in the config file
+    #[serde(default)]
+    pub mcp_servers: Vec<McpServerConfig>,
+    /// Engines and models to try, in order, when the main one fails with a temporary error; only settable in the config file
+    #[serde(default)]
+    pub fallback_models: Vec<FallbackModel>,
+    /// Price per million tokens for each model, for cost accounting
+    #[serde(default)]
+    pub prices: HashMap<String, ModelPrice>,
+    pub daily_budget: Option<f64>,
+    pub usage_ledger: Option<String>,
+    pub cassette: Option<String>,
+    pub cassette_mode: String,
     pub log_level: String,
     pub trigger_corner: String,
+    pub idle_trigger: Option<u64>,
+    pub idle_min_change: f64,
+    /// What each touch gesture does, like `two-finger-tap = "undo"`; only settable in the config file
+    #[serde(default)]
+    pub gestures: HashMap<String, String>,
+    /// More places to tap, each with its own prompt and model; only settable in the config file
+    #[serde(default)]
+    pub zones: Vec<TriggerZone>,
 }
 
 impl Default for Config {
@@ -46,27 +141,53 @@ impl Default for Config {
             no_draw: false,
             no_svg: false,
             no_keyboard: false,
+            svg_mode: "vector".to_string(),
+            fill_mode: "scanline".to_string(),
+            fill_spacing: 6,
             no_draw_progress: false,
             input_png: None,
             output_file: None,
             model_output_file: None,
+            model_output_include_images: false,
             save_screenshot: None,
             save_bitmap: None,
             no_loop: false,
             no_trigger: false,
             apply_segmentation: false,
+            crop_new_ink: false,
+            conversation: false,
+            stream: false,
+            max_tool_steps: 5,
+            max_history_images: 4,
+            max_retries: 3,
             web_search: false,
             thinking: false,
             thinking_tokens: 5000,
+            reasoning_effort: None,
+            builtin_tools: None,
+            no_stateful: false,
+            tool_mode: "auto".to_string(),
+            mcp_servers: Vec::new(),
+            fallback_models: Vec::new(),
+            prices: default_prices(),
+            daily_budget: None,
+            usage_ledger: None,
+            cassette: None,
+            cassette_mode: "replay".to_string(),
             log_level: "info".to_string(),
             trigger_corner: "UR".to_string(),
+            idle_trigger: None,
+            idle_min_change: 0.05,
+            gestures: HashMap::from([("corner-tap".to_string(), "prompt".to_string())]),
+            zones: Vec::new(),
         }
     }
 }
 
 impl Config {
-    /// Load configuration using figment (file -> env -> CLI precedence)
-    pub fn load<T: Serialize>(args: &T) -> Result<Self> {
+    /// Load configuration using figment (file -> env -> CLI precedence). The
+    /// log level comes apart from `args` since it's an option of every subcommand.
+    pub fn load<T: Serialize>(args: &T, log_level: &str) -> Result<Self> {
         let config: Self = Figment::new()
             // Start with built-in defaults
             .merge(Serialized::defaults(Config::default()))
@@ -76,6 +197,7 @@ impl Config {
             .merge(Env::prefixed("GHOSTWRITER_"))
             // Finally CLI arguments (highest precedence)
             .merge(Serialized::globals(args))
+            .merge(Serialized::global("log_level", log_level))
             .extract()
             .map_err(|e| anyhow::anyhow!("Configuration error: {}", e))?;
 
@@ -107,12 +229,102 @@ impl Config {
         // Validate trigger corner
         TriggerCorner::from_string(&self.trigger_corner)?;
 
+        // Validate gesture names and actions
+        parse_gesture_actions(&self.gestures)?;
+
+        if self.idle_trigger == Some(0) {
+            return Err(anyhow::anyhow!("idle_trigger must be greater than 0"));
+        }
+        if !(0.0..=100.0).contains(&self.idle_min_change) {
+            return Err(anyhow::anyhow!("idle_min_change must be a percentage from 0 to 100"));
+        }
+
+        // Validate trigger zones
+        for (index, zone) in self.zones.iter().enumerate() {
+            if zone.name.is_empty() {
+                return Err(anyhow::anyhow!("zones entry {} needs a name", index + 1));
+            }
+            if self.zones[..index].iter().any(|other| other.name == zone.name) {
+                return Err(anyhow::anyhow!("Duplicate zone name: {}", zone.name));
+            }
+            zone.area()?;
+        }
+
+        // Validate SVG drawing mode
+        match self.svg_mode.as_str() {
+            "vector" | "raster" => {}
+            _ => return Err(anyhow::anyhow!("Invalid svg_mode: {}. Use vector or raster", self.svg_mode)),
+        }
+
+        // Validate raster fill settings
+        FillMode::from_string(&self.fill_mode)?;
+        if self.fill_spacing == 0 {
+            return Err(anyhow::anyhow!("fill_spacing must be greater than 0"));
+        }
+
+        // Validate reasoning effort
+        if let Some(reasoning_effort) = &self.reasoning_effort {
+            match reasoning_effort.as_str() {
+                "minimal" | "low" | "medium" | "high" => {}
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "Invalid reasoning_effort: {}. Use minimal, low, medium, or high",
+                        reasoning_effort
+                    ))
+                }
+            }
+        }
+
+        // Validate how tools are offered to Ollama models
+        match self.tool_mode.as_str() {
+            "auto" | "native" | "prompt" => {}
+            _ => return Err(anyhow::anyhow!("Invalid tool_mode: {}. Use auto, native, or prompt", self.tool_mode)),
+        }
+
+        // Validate cassette mode
+        match self.cassette_mode.as_str() {
+            "record" | "replay" => {}
+            _ => return Err(anyhow::anyhow!("Invalid cassette_mode: {}. Use record or replay", self.cassette_mode)),
+        }
+
+        // Validate MCP servers
+        for (index, server) in self.mcp_servers.iter().enumerate() {
+            if server.name.is_empty() || server.command.is_empty() {
+                return Err(anyhow::anyhow!("mcp_servers entry {} needs both a name and a command", index + 1));
+            }
+            if self.mcp_servers[..index].iter().any(|other| other.name == server.name) {
+                return Err(anyhow::anyhow!("Duplicate MCP server name: {}", server.name));
+            }
+            if !matches!(server.next_action.as_str(), "loop" | "end") {
+                return Err(anyhow::anyhow!(
+                    "Invalid next_action for MCP server {}: {}. Use loop or end",
+                    server.name,
+                    server.next_action
+                ));
+            }
+        }
+
+        if self.fallback_models.iter().any(|fallback| fallback.model.is_empty()) {
+            return Err(anyhow::anyhow!("Every fallback_models entry needs a model"));
+        }
+
+        if self.daily_budget.is_some_and(|budget| budget <= 0.0) {
+            return Err(anyhow::anyhow!("daily_budget must be greater than 0"));
+        }
+        if let Some((model, _)) = self.prices.iter().find(|(_, price)| price.input < 0.0 || price.output < 0.0) {
+            return Err(anyhow::anyhow!("Price for {} can't be negative", model));
+        }
+
         // Validate log level
         match self.log_level.as_str() {
             "error" | "warn" | "info" | "debug" | "trace" => {}
             _ => return Err(anyhow::anyhow!("Invalid log level: {}", self.log_level)),
         }
 
+        if self.max_tool_steps == 0 {
+            return Err(anyhow::anyhow!("max_tool_steps must be greater than 0"));
+        }
+
         // Validate thinking tokens
         if self.thinking_tokens == 0 {
             return Err(anyhow::anyhow!("thinking_tokens must be greater than 0"));
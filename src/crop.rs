@@ -0,0 +1,162 @@
+use anyhow::Result;
+use image::{imageops, DynamicImage, GrayImage, ImageFormat};
+use std::io::Cursor;
+
+// Output dimensions remain the same for both devices
+const VIRTUAL_WIDTH: u32 = 768;
+const VIRTUAL_HEIGHT: u32 = 1024;
+
+/// Room left around the new ink, in virtual pixels
+const PADDING: u32 = 24;
+/// Rows and columns with fewer new ink pixels than this are noise, not writing
+const MIN_INK_PER_LINE: usize = 2;
+/// When the new ink covers more of the page than this, the whole page is just as good
+const MAX_CROP_FRACTION: f32 = 0.5;
+
+/// The ink added to a page since it was last seen: a crop around it at full
+/// resolution, and the whole page at half resolution for context
+pub struct NewInk {
+    pub crop_png: Vec<u8>,
+    pub context_png: Vec<u8>,
+    /// Where the crop is on the page, as x, y, width, height in virtual pixels (768x1024)
+    pub bounds: (u32, u32, u32, u32),
+}
+
+impl NewInk {
+    /// Find the ink in `current_png` that isn't in `previous_png`. None when
+    /// there isn't any, or there's so much that the whole page should be sent.
+    pub fn find(previous_png: &[u8], current_png: &[u8]) -> Result<Option<Self>> {
+        let previous = image::load_from_memory(previous_png)?.to_luma8();
+        let current = image::load_from_memory(current_png)?.to_luma8();
+        if previous.dimensions() != current.dimensions() {
+            return Ok(None);
+        }
+        let (width, height) = current.dimensions();
+
+        let mut row_ink = vec![0; height as usize];
+        let mut column_ink = vec![0; width as usize];
+        for (x, y, pixel) in current.enumerate_pixels() {
+            if pixel[0] < 128 && previous.get_pixel(x, y)[0] >= 128 {
+                row_ink[y as usize] += 1;
+                column_ink[x as usize] += 1;
+            }
+        }
+        let (Some((left, right)), Some((top, bottom))) = (ink_span(&column_ink), ink_span(&row_ink)) else {
+            return Ok(None);
+        };
+
+        let padding = PADDING * width / VIRTUAL_WIDTH;
+        let (left, top) = (left.saturating_sub(padding), top.saturating_sub(padding));
+        let (right, bottom) = ((right + padding).min(width - 1), (bottom + padding).min(height - 1));
+        let (crop_width, crop_height) = (right - left + 1, bottom - top + 1);
+        if (crop_width * crop_height) as f32 > (width * height) as f32 * MAX_CROP_FRACTION {
+            return Ok(None);
+        }
+
+        let crop = imageops::crop_imm(&current, left, top, crop_width, crop_height).to_image();
+        let context = imageops::resize(&current, width / 2, height / 2, imageops::FilterType::Triangle);
+
+        let to_virtual_x = |x: u32| x * VIRTUAL_WIDTH / width;
+        let to_virtual_y = |y: u32| y * VIRTUAL_HEIGHT / height;
+        Ok(Some(Self {
+            crop_png: encode_png(crop)?,
+            context_png: encode_png(context)?,
+            bounds: (
+                to_virtual_x(left),
+                to_virtual_y(top),
+                to_virtual_x(crop_width).max(1),
+                to_virtual_y(crop_height).max(1),
+            ),
+        }))
+    }
+
+    /// Tells the model what the two images are, to go after them
+    pub fn description(&self) -> String {
+        let (x, y, width, height) = self.bounds;
+        format!(
+            "The first image is the whole page at half resolution, for context. \
+            The second image is the newest writing on the page at full resolution: \
+            it covers x {} to {} and y {} to {} of the {}x{} page. \
+            Focus on the newest writing, and use page coordinates, not coordinates within the crop, for anything you draw.",
+            x,
+            x + width,
+            y,
+            y + height,
+            VIRTUAL_WIDTH,
+            VIRTUAL_HEIGHT
+        )
+    }
+}
+
+/// First and last index with enough ink
+fn ink_span(counts: &[usize]) -> Option<(u32, u32)> {
+    let first = counts.iter().position(|&count| count >= MIN_INK_PER_LINE)?;
+    let last = counts.iter().rposition(|&count| count >= MIN_INK_PER_LINE)?;
+    Some((first as u32, last as u32))
+}
+
+fn encode_png(image: GrayImage) -> Result<Vec<u8>> {
+    let mut png = Vec::new();
+    DynamicImage::ImageLuma8(image).write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    /// A white page with black rectangles, each x, y, width, height
+    fn page(width: u32, height: u32, rects: &[(u32, u32, u32, u32)]) -> Vec<u8> {
+        let mut image = GrayImage::from_pixel(width, height, Luma([255]));
+        for &(x, y, w, h) in rects {
+            for py in y..y + h {
+                for px in x..x + w {
+                    image.put_pixel(px, py, Luma([0]));
+                }
+            }
+        }
+        encode_png(image).unwrap()
+    }
+
+    #[test]
+    fn crops_around_the_new_ink() {
+        let old_ink = (400, 600, 200, 100);
+        let previous = page(768, 1024, &[old_ink]);
+        let current = page(768, 1024, &[old_ink, (100, 300, 100, 40)]);
+
+        let new_ink = NewInk::find(&previous, &current).unwrap().unwrap();
+        assert_eq!(new_ink.bounds, (76, 276, 148, 88));
+        let crop = image::load_from_memory(&new_ink.crop_png).unwrap();
+        assert_eq!((crop.width(), crop.height()), (148, 88));
+        let context = image::load_from_memory(&new_ink.context_png).unwrap();
+        assert_eq!((context.width(), context.height()), (384, 512));
+    }
+
+    #[test]
+    fn reports_bounds_in_virtual_pixels() {
+        // The reMarkable 2 screenshot resolution
+        let previous = page(1404, 1872, &[]);
+        let current = page(1404, 1872, &[(702, 936, 100, 100)]);
+        // 100 pixels of ink plus 43 of padding on each side, scaled down
+        assert_eq!(NewInk::find(&previous, &current).unwrap().unwrap().bounds, (360, 488, 101, 101));
+    }
+
+    #[test]
+    fn ignores_unchanged_pages_and_specks() {
+        let previous = page(768, 1024, &[(10, 10, 50, 50)]);
+        assert!(NewInk::find(&previous, &previous).unwrap().is_none());
+        let speck = page(768, 1024, &[(10, 10, 50, 50), (300, 300, 1, 1)]);
+        assert!(NewInk::find(&previous, &speck).unwrap().is_none());
+    }
+
+    #[test]
+    fn sends_the_whole_page_for_too_much_new_ink() {
+        let previous = page(768, 1024, &[]);
+        // Just under half the page, but over half once padded
+        let almost_half = page(768, 1024, &[(0, 0, 768, 500)]);
+        assert!(NewInk::find(&previous, &almost_half).unwrap().is_none());
+        let under_half = page(768, 1024, &[(0, 0, 768, 480)]);
+        assert!(NewInk::find(&previous, &under_half).unwrap().is_some());
+    }
+}
@@ -16,11 +16,16 @@ pub fn get_uinput_module_data(version: &str) -> Option<Vec<u8>> {
 }
 
 pub fn load_config(filename: &str) -> String {
+    try_load_config(filename).unwrap_or_else(|| panic!("Config file {} not found", filename))
+}
+
+/// Like `load_config`, but returns None when neither a local nor a bundled file exists
+pub fn try_load_config(filename: &str) -> Option<String> {
     log::debug!("Loading config from {}", filename);
 
     if std::path::Path::new(filename).exists() {
-        std::fs::read_to_string(filename).unwrap()
+        std::fs::read_to_string(filename).ok()
     } else {
-        std::str::from_utf8(AssetPrompts::get(filename).unwrap().data.as_ref()).unwrap().to_string()
+        AssetPrompts::get(filename).map(|asset| String::from_utf8_lossy(asset.data.as_ref()).to_string())
     }
 }
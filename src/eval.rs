@@ -0,0 +1,709 @@
+use anyhow::Result;
+use image::{imageops, Rgba};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as json;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::FallbackModel;
+use crate::expectations::{CheckResult, Expectations, Response};
+use crate::judge::{Judge, Judgement};
+use crate::usage::civil_from_days;
+
+/// The test matrix: which scenarios to run, with which settings, how many times.
+/// Each scenario is a directory under `scenario_dir` holding an `input.png`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct EvalMatrix {
+    #[serde(default = "default_scenario_dir")]
+    pub scenario_dir: String,
+    #[serde(default = "default_output_dir")]
+    pub output_dir: String,
+    #[serde(default = "default_attempts")]
+    pub attempts: u32,
+    /// Pause between runs, to stay clear of rate limits
+    #[serde(default)]
+    pub delay_seconds: u64,
+    /// Scenario names to run; all of them when empty
+    #[serde(default)]
+    pub scenarios: Vec<String>,
+    /// Extra command line arguments for every case
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Score each run against the scenario's rubric.md with this model
+    #[serde(default)]
+    pub judge: Option<FallbackModel>,
+    pub cases: Vec<EvalCase>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct EvalCase {
+    pub name: String,
+    /// Command line arguments for this case, like `["--model", "gpt-4o"]`
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+fn default_scenario_dir() -> String {
+    "evaluations".to_string()
+}
+
+fn default_output_dir() -> String {
+    "evaluation_results".to_string()
+}
+
+fn default_attempts() -> u32 {
+    3
+}
+
+impl EvalMatrix {
+    pub fn load(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Failed to read eval matrix {}: {}", path, e))?;
+        let matrix: Self = toml::from_str(&content).map_err(|e| anyhow::anyhow!("Failed to parse eval matrix {}: {}", path, e))?;
+        if matrix.cases.is_empty() {
+            return Err(anyhow::anyhow!("Eval matrix {} has no [[cases]]", path));
+        }
+        Ok(matrix)
+    }
+
+    /// The scenarios to run, in name order
+    pub fn scenario_names(&self) -> Result<Vec<String>> {
+        if !self.scenarios.is_empty() {
+            return Ok(self.scenarios.clone());
+        }
+        let mut names = std::fs::read_dir(&self.scenario_dir)
+            .map_err(|e| anyhow::anyhow!("Failed to read scenario directory {}: {}", self.scenario_dir, e))?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().join("input.png").is_file())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(names)
+    }
+}
+
+/// One run of one case against one scenario
+struct EvalRun {
+    case: String,
+    attempt: u32,
+    dir: PathBuf,
+    success: bool,
+    duration: Duration,
+    judgement: Option<Judgement>,
+    checks: Option<Vec<CheckResult>>,
+}
+
+impl EvalRun {
+    fn passed_checks(&self) -> Option<bool> {
+        self.checks.as_ref().map(|checks| checks.iter().all(|check| check.passed))
+    }
+}
+
+/// Run every case of the matrix against every scenario, and write an HTML report
+/// of the results. Returns the path of the report.
+pub fn run_eval(matrix: &EvalMatrix) -> Result<PathBuf> {
+    let scenarios = matrix.scenario_names()?;
+    let outdir_base = Path::new(&matrix.output_dir).join(timestamp());
+    std::fs::create_dir_all(&outdir_base)?;
+
+    let total = scenarios.len() * matrix.cases.len() * matrix.attempts as usize;
+    info!(
+        "There are {} scenarios and {} test cases with {} attempts ({} total tests). Results in {:?}",
+        scenarios.len(),
+        matrix.cases.len(),
+        matrix.attempts,
+        total,
+        outdir_base
+    );
+
+    // Run ourselves for each case, just like on the device but with the screen swapped for the scenario image
+    let exe = std::env::current_exe()?;
+    let mut judge = matrix.judge.as_ref().map(Judge::new).transpose()?;
+    let mut results = Vec::new();
+    let mut count = 0;
+    for scenario in &scenarios {
+        let input = Path::new(&matrix.scenario_dir).join(scenario).join("input.png");
+        std::fs::create_dir_all(outdir_base.join(scenario))?;
+        std::fs::copy(&input, outdir_base.join(scenario).join("input.png"))?;
+        let rubric = std::fs::read_to_string(Path::new(&matrix.scenario_dir).join(scenario).join("rubric.md")).ok();
+        let expectations = Expectations::load(&Path::new(&matrix.scenario_dir).join(scenario))?;
+        if judge.is_some() && rubric.is_none() {
+            warn!("Scenario {} has no rubric.md, its runs won't be scored", scenario);
+        }
+
+        let mut runs = Vec::new();
+        for case in &matrix.cases {
+            for attempt in 1..=matrix.attempts {
+                if count > 0 && matrix.delay_seconds > 0 {
+                    sleep(Duration::from_secs(matrix.delay_seconds));
+                }
+                count += 1;
+                info!("[{}/{}] Running scenario {} with {} attempt {}", count, total, scenario, case.name, attempt);
+
+                let dir = outdir_base.join(scenario).join(&case.name).join(attempt.to_string());
+                std::fs::create_dir_all(&dir)?;
+                let mut run = run_case(&exe, &input, &matrix.args, case, attempt, &dir)?;
+                if let Some(expectations) = &expectations {
+                    run.checks = check_run(expectations, &run, &input);
+                }
+                if let (Some(judge), Some(rubric)) = (judge.as_mut(), &rubric) {
+                    run.judgement = judge_run(judge, &run, &input, rubric);
+                }
+                runs.push(run);
+            }
+        }
+        results.push((scenario.clone(), runs));
+    }
+
+    let scores = case_scores(matrix, &results);
+    if scores.iter().any(|score| score.judged > 0 || score.checked > 0) {
+        for score in &scores {
+            info!(
+                "{}: passed checks in {}/{} runs, mean score {} over {} judged runs{}",
+                score.case,
+                score.passed,
+                score.checked,
+                score.mean.map_or("-".to_string(), |mean| format!("{:.2}", mean)),
+                score.judged,
+                score.change.map_or(String::new(), |change| format!(" ({:+.1}% vs {})", change, scores[0].case))
+            );
+        }
+        std::fs::write(outdir_base.join("scores.json"), serde_json::to_string_pretty(&scores)?)?;
+    }
+
+    let report = outdir_base.join("report.html");
+    write_report(&report, &outdir_base, matrix, &results, &scores)?;
+    Ok(report)
+}
+
+fn run_case(exe: &Path, input: &Path, shared_args: &[String], case: &EvalCase, attempt: u32, dir: &Path) -> Result<EvalRun> {
+    let started = Instant::now();
+    let output = Command::new(exe)
+        .arg("once")
+        .arg(input)
+        .arg("--model-output-file")
+        .arg(dir.join("result.json"))
+        .arg("--output-file")
+        .arg(dir.join("result.out"))
+        .arg("--save-bitmap")
+        .arg(dir.join("result.png"))
+        .args(["--no-draw", "--no-draw-progress"])
+        .args(shared_args)
+        .args(&case.args)
+        .output()?;
+    let duration = started.elapsed();
+
+    let mut log = output.stdout;
+    log.extend_from_slice(&output.stderr);
+    std::fs::write(dir.join("log.txt"), &log)?;
+    if !output.status.success() {
+        warn!("Run failed ({}), see {:?}", output.status, dir.join("log.txt"));
+    }
+
+    let result = dir.join("result.png");
+    if result.is_file() {
+        if let Err(e) = merge_overlay(input, &result, &dir.join("merged-output.png")) {
+            warn!("Failed to merge {:?} onto the input: {}", result, e);
+        }
+    }
+
+    Ok(EvalRun {
+        case: case.name.clone(),
+        attempt,
+        dir: dir.to_path_buf(),
+        success: output.status.success(),
+        duration,
+        judgement: None,
+        checks: None,
+    })
+}
+
+/// Check a run against the scenario's expectations, saving the results next to the run's other results
+fn check_run(expectations: &Expectations, run: &EvalRun, input: &Path) -> Option<Vec<CheckResult>> {
+    let model_output = read_model_output(&run.dir);
+    let tool_calls = model_output["tool_calls"].as_array().cloned().unwrap_or_default();
+    let tools = tool_calls
+        .iter()
+        .filter_map(|call| call["name"].as_str().map(|name| name.to_string()))
+        .collect::<Vec<_>>();
+    let text = tool_calls
+        .iter()
+        .filter(|call| call["name"] == "draw_text")
+        .filter_map(|call| call["arguments"]["text"].as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let bitmap = run.dir.join("result.png");
+
+    let response = Response {
+        bitmap: bitmap.is_file().then_some(bitmap.as_path()),
+        text: &text,
+        tools: &tools,
+    };
+    match expectations.check(input, &response) {
+        Ok(checks) => {
+            info!("Passed {}/{} checks", checks.iter().filter(|check| check.passed).count(), checks.len());
+            if let Ok(content) = serde_json::to_string_pretty(&checks) {
+                std::fs::write(run.dir.join("checks.json"), content).ok();
+            }
+            Some(checks)
+        }
+        Err(e) => {
+            warn!("Failed to check {:?}: {}", run.dir, e);
+            None
+        }
+    }
+}
+
+/// Have the judge score a run, saving the verdict next to the run's other results
+fn judge_run(judge: &mut Judge, run: &EvalRun, input: &Path, rubric: &str) -> Option<Judgement> {
+    // Judge the page with the response drawn in, or just the page when the response was typed
+    let merged = run.dir.join("merged-output.png");
+    let image = std::fs::read(if merged.is_file() { merged.as_path() } else { input }).ok()?;
+
+    let model_output = read_model_output(&run.dir);
+    let mut response = match model_output["function"].as_str() {
+        Some(function) => format!(
+            "Called {} with:\n{}",
+            function,
+            serde_json::to_string_pretty(&model_output["arguments"]).unwrap_or_default()
+        ),
+        None => "Did not call any tool.".to_string(),
+    };
+    if let Some(error) = model_output["error"].as_str() {
+        write!(response, "\nFailed with: {}", error).ok();
+    }
+
+    match judge.judge(&image, rubric, &response) {
+        Ok(judgement) => {
+            info!("Scored {}: {}", judgement.score, judgement.rationale);
+            if let Ok(content) = serde_json::to_string_pretty(&judgement) {
+                std::fs::write(run.dir.join("judge.json"), content).ok();
+            }
+            Some(judgement)
+        }
+        Err(e) => {
+            warn!("Failed to judge {:?}: {}", run.dir, e);
+            None
+        }
+    }
+}
+
+/// The judge's scores for one case, across every scenario and attempt
+#[derive(Serialize)]
+struct CaseScore {
+    case: String,
+    /// Runs checked against expectations, and how many of them passed every check
+    checked: usize,
+    passed: usize,
+    judged: usize,
+    mean: Option<f64>,
+    /// Percent better (or worse) than the first case in the matrix
+    change: Option<f64>,
+    scenarios: Vec<ScenarioScore>,
+}
+
+#[derive(Serialize)]
+struct ScenarioScore {
+    scenario: String,
+    mean: Option<f64>,
+}
+
+fn case_scores(matrix: &EvalMatrix, results: &[(String, Vec<EvalRun>)]) -> Vec<CaseScore> {
+    let scores_for = |case: &str, scenario: Option<&str>| {
+        results
+            .iter()
+            .filter(|(name, _)| scenario.is_none_or(|scenario| scenario == name))
+            .flat_map(|(_, runs)| runs.iter())
+            .filter(|run| run.case == case)
+            .filter_map(|run| run.judgement.as_ref().map(|judgement| judgement.score))
+            .collect::<Vec<_>>()
+    };
+    let mean = |scores: &[f64]| (!scores.is_empty()).then(|| scores.iter().sum::<f64>() / scores.len() as f64);
+
+    let mut scores = matrix
+        .cases
+        .iter()
+        .map(|case| {
+            let all = scores_for(&case.name, None);
+            let checked = results
+                .iter()
+                .flat_map(|(_, runs)| runs.iter())
+                .filter(|run| run.case == case.name)
+                .filter_map(|run| run.passed_checks())
+                .collect::<Vec<_>>();
+            CaseScore {
+                case: case.name.clone(),
+                checked: checked.len(),
+                passed: checked.iter().filter(|passed| **passed).count(),
+                judged: all.len(),
+                mean: mean(&all),
+                change: None,
+                scenarios: results
+                    .iter()
+                    .map(|(scenario, _)| ScenarioScore {
+                        scenario: scenario.clone(),
+                        mean: mean(&scores_for(&case.name, Some(scenario))),
+                    })
+                    .collect(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(baseline) = scores.first().and_then(|score| score.mean).filter(|baseline| *baseline > 0.0) {
+        for score in scores.iter_mut().skip(1) {
+            score.change = score.mean.map(|mean| (mean - baseline) / baseline * 100.0);
+        }
+    }
+    scores
+}
+
+/// Lay the ink of `result` over `input` in red, so it's easy to see what the model added
+pub fn merge_overlay(input: &Path, result: &Path, output: &Path) -> Result<()> {
+    let mut merged = image::open(input)?.to_rgba8();
+    let mut ink = image::open(result)?.to_luma8();
+    if ink.dimensions() != merged.dimensions() {
+        ink = imageops::resize(&ink, merged.width(), merged.height(), imageops::FilterType::Nearest);
+    }
+
+    for (x, y, pixel) in ink.enumerate_pixels() {
+        if pixel[0] < 128 {
+            merged.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+        }
+    }
+
+    merged.save(output)?;
+    Ok(())
+}
+
+fn write_report(report: &Path, outdir_base: &Path, matrix: &EvalMatrix, results: &[(String, Vec<EvalRun>)], scores: &[CaseScore]) -> Result<()> {
+    let relative = |path: &Path| path.strip_prefix(outdir_base).unwrap_or(path).to_string_lossy().to_string();
+
+    let mut html = String::new();
+    writeln!(html, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset='utf-8'>")?;
+    // The results directory is named after the time the evaluation started, in UTC
+    writeln!(html, "<title>Ghostwriter evaluation {} UTC</title>", escape_html(&relative_name(outdir_base)))?;
+    writeln!(
+        html,
+        "<style>body {{ font-family: sans-serif; }} td {{ vertical-align: top; padding: 4px; }} img {{ border: 1px solid #888; width: 200px; }} pre {{ width: 200px; white-space: pre-wrap; font-size: 10px; }} .caption {{ font-size: 11px; color: #444; width: 200px; }} .failed {{ color: #b00; }}</style>"
+    )?;
+    writeln!(html, "</head>\n<body>")?;
+    writeln!(html, "<h1>Ghostwriter evaluation {} UTC</h1>", escape_html(&relative_name(outdir_base)))?;
+    writeln!(
+        html,
+        "<p>{} scenarios and {} test cases with {} attempts.</p>",
+        results.len(),
+        matrix.cases.len(),
+        matrix.attempts
+    )?;
+
+    if scores.iter().any(|score| score.judged > 0 || score.checked > 0) {
+        let baseline = &scores[0].case;
+        writeln!(html, "<h2>Scores</h2>\n<table>")?;
+        writeln!(
+            html,
+            "<tr><th>Case</th><th>Passed checks</th><th>Judged runs</th><th>Mean score</th><th>vs {}</th>{}</tr>",
+            escape_html(baseline),
+            results
+                .iter()
+                .map(|(scenario, _)| format!("<th>{}</th>", escape_html(scenario)))
+                .collect::<String>()
+        )?;
+        for score in scores {
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>{}</tr>",
+                escape_html(&score.case),
+                if score.checked > 0 {
+                    format!("{}/{}", score.passed, score.checked)
+                } else {
+                    "-".to_string()
+                },
+                score.judged,
+                format_score(score.mean),
+                score.change.map_or("-".to_string(), |change| format!("{:+.1}%", change)),
+                score
+                    .scenarios
+                    .iter()
+                    .map(|scenario| format!("<td>{}</td>", format_score(scenario.mean)))
+                    .collect::<String>()
+            )?;
+        }
+        writeln!(html, "</table>")?;
+    }
+
+    for (scenario, runs) in results {
+        writeln!(html, "<h2>{}</h2>\n<table>", escape_html(scenario))?;
+        for case in &matrix.cases {
+            writeln!(
+                html,
+                "<tr><th>{}<br><code>{}</code></th>",
+                escape_html(&case.name),
+                escape_html(&case.args.join(" "))
+            )?;
+            for run in runs.iter().filter(|run| run.case == case.name) {
+                writeln!(html, "<td>")?;
+                let merged = run.dir.join("merged-output.png");
+                if merged.is_file() {
+                    writeln!(html, "<a href='{0}'><img src='{0}'></a>", escape_html(&relative(&merged)))?;
+                } else {
+                    writeln!(html, "<img src='{}/input.png'>", escape_html(scenario))?;
+                    let output = std::fs::read_to_string(run.dir.join("result.out")).unwrap_or_default();
+                    writeln!(html, "<pre>{}</pre>", escape_html(output.trim()))?;
+                }
+                writeln!(html, "<div class='caption'>{}</div>", caption(run))?;
+                writeln!(html, "</td>")?;
+            }
+            writeln!(html, "</tr>")?;
+        }
+        writeln!(html, "</table>")?;
+    }
+
+    writeln!(html, "</body>\n</html>")?;
+    std::fs::write(report, html)?;
+    Ok(())
+}
+
+/// A run's --model-output-file, or null if it didn't get that far
+fn read_model_output(dir: &Path) -> json {
+    std::fs::read_to_string(dir.join("result.json"))
+        .ok()
+        .and_then(|content| serde_json::from_str::<json>(&content).ok())
+        .unwrap_or(json::Null)
+}
+
+fn format_score(score: Option<f64>) -> String {
+    score.map_or("-".to_string(), |score| format!("{:.1}", score))
+}
+
+/// Attempt number, tool, tokens, timing, checks and score for a run
+fn caption(run: &EvalRun) -> String {
+    let model_output = read_model_output(&run.dir);
+
+    let mut parts = vec![format!("#{}", run.attempt)];
+    if let Some(function) = model_output["function"].as_str() {
+        parts.push(escape_html(function));
+    }
+    if let (Some(input), Some(output)) = (model_output["usage"]["input_tokens"].as_u64(), model_output["usage"]["output_tokens"].as_u64()) {
+        parts.push(format!("{}/{} tokens", input, output));
+    }
+    parts.push(format!("{:.1}s", run.duration.as_secs_f32()));
+    if let Some(checks) = &run.checks {
+        let passed = checks.iter().filter(|check| check.passed).count();
+        parts.push(format!("<b>checks {}/{}</b>", passed, checks.len()));
+    }
+    if let Some(judgement) = &run.judgement {
+        parts.push(format!("<b>score {:.1}</b>", judgement.score));
+    }
+
+    let mut caption = parts.join(" &middot; ");
+    for check in run.checks.iter().flatten().filter(|check| !check.passed) {
+        write!(
+            caption,
+            "<br><span class='failed'>{} ({})</span>",
+            escape_html(&check.check),
+            escape_html(&check.detail)
+        )
+        .ok();
+    }
+    if let Some(judgement) = &run.judgement {
+        write!(caption, "<br>{}", escape_html(&judgement.rationale)).ok();
+    }
+    if let Some(error) = model_output["error"].as_str() {
+        write!(caption, "<br><span class='failed'>{}</span>", escape_html(error)).ok();
+    } else if !run.success {
+        caption.push_str("<br><span class='failed'>failed, see log.txt</span>");
+    }
+    caption
+}
+
+fn relative_name(path: &Path) -> String {
+    path.file_name().map_or_else(String::new, |name| name.to_string_lossy().to_string())
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\'', "&#39;")
+        .replace('"', "&quot;")
+}
+
+/// The current UTC time as YYYY-MM-DD_HH-MM-SS, for naming result directories
+fn timestamp() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let time = seconds % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma, RgbaImage};
+
+    /// A scratch directory for one test
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ghostwriter-eval-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn matrix(toml: &str) -> EvalMatrix {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn overlays_only_the_ink_in_red() {
+        let dir = scratch("overlay");
+        RgbaImage::from_pixel(4, 4, Rgba([200, 200, 200, 255])).save(dir.join("input.png")).unwrap();
+        let mut result = GrayImage::from_pixel(4, 4, Luma([255]));
+        result.put_pixel(1, 2, Luma([0]));
+        result.save(dir.join("result.png")).unwrap();
+
+        merge_overlay(&dir.join("input.png"), &dir.join("result.png"), &dir.join("merged.png")).unwrap();
+        let merged = image::open(dir.join("merged.png")).unwrap().to_rgba8();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(merged.get_pixel(1, 2), &Rgba([255, 0, 0, 255]));
+        let red = merged.pixels().filter(|pixel| **pixel == Rgba([255, 0, 0, 255])).count();
+        assert_eq!(red, 1);
+        assert_eq!(merged.get_pixel(0, 0), &Rgba([200, 200, 200, 255]));
+    }
+
+    #[test]
+    fn overlays_a_result_of_another_size() {
+        let dir = scratch("overlay-resized");
+        RgbaImage::from_pixel(4, 4, Rgba([255, 255, 255, 255])).save(dir.join("input.png")).unwrap();
+        // Twice the size, with ink in the top left quarter
+        let mut result = GrayImage::from_pixel(8, 8, Luma([255]));
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1), (2, 2), (3, 3), (2, 3), (3, 2)] {
+            result.put_pixel(x, y, Luma([0]));
+        }
+        result.save(dir.join("result.png")).unwrap();
+
+        merge_overlay(&dir.join("input.png"), &dir.join("result.png"), &dir.join("merged.png")).unwrap();
+        let merged = image::open(dir.join("merged.png")).unwrap().to_rgba8();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(merged.dimensions(), (4, 4));
+        assert_eq!(merged.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(merged.get_pixel(1, 1), &Rgba([255, 0, 0, 255]));
+        assert_eq!(merged.get_pixel(3, 3), &Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn a_matrix_needs_cases() {
+        let dir = scratch("matrix");
+        let path = dir.join("matrix.toml");
+        std::fs::write(&path, "attempts = 2\n").unwrap();
+        let empty = EvalMatrix::load(path.to_str().unwrap()).unwrap_err().to_string();
+        std::fs::write(&path, "attempts = 2\ncases = []\n").unwrap();
+        let no_cases = EvalMatrix::load(path.to_str().unwrap()).unwrap_err().to_string();
+        std::fs::write(&path, "[[cases]]\nname = \"default\"\n").unwrap();
+        let loaded = EvalMatrix::load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert!(empty.contains("Failed to parse eval matrix"), "{}", empty);
+        assert!(no_cases.contains("has no [[cases]]"), "{}", no_cases);
+        assert_eq!((loaded.attempts, loaded.scenario_dir.as_str()), (3, "evaluations"));
+    }
+
+    #[test]
+    fn finds_scenarios_with_an_input() {
+        let dir = scratch("scenarios");
+        for name in ["zebra", "apple", "no_input"] {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+        }
+        std::fs::write(dir.join("zebra/input.png"), "").unwrap();
+        std::fs::write(dir.join("apple/input.png"), "").unwrap();
+        std::fs::write(dir.join("no_input/rubric.md"), "").unwrap();
+
+        let all = matrix(&format!("scenario_dir = {:?}\n[[cases]]\nname = \"a\"\n", dir))
+            .scenario_names()
+            .unwrap();
+        let chosen = matrix(&format!("scenario_dir = {:?}\nscenarios = [\"zebra\"]\n[[cases]]\nname = \"a\"\n", dir))
+            .scenario_names()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(all, vec!["apple", "zebra"]);
+        assert_eq!(chosen, vec!["zebra"]);
+    }
+
+    /// A run of `case` with this judge's score, if it was judged
+    fn run(case: &str, score: Option<f64>) -> EvalRun {
+        EvalRun {
+            case: case.to_string(),
+            attempt: 1,
+            dir: PathBuf::new(),
+            success: true,
+            duration: Duration::ZERO,
+            judgement: score.map(|score| Judgement {
+                score,
+                rationale: String::new(),
+                model: "judge".to_string(),
+            }),
+            checks: None,
+        }
+    }
+
+    fn three_cases() -> EvalMatrix {
+        matrix("[[cases]]\nname = \"base\"\n[[cases]]\nname = \"better\"\n[[cases]]\nname = \"unjudged\"\n")
+    }
+
+    #[test]
+    fn scores_each_case_against_the_first() {
+        let results = vec![
+            (
+                "box".to_string(),
+                vec![run("base", Some(4.0)), run("base", Some(6.0)), run("better", Some(8.0)), run("unjudged", None)],
+            ),
+            ("list".to_string(), vec![run("base", Some(2.0)), run("better", Some(4.0)), run("better", None)]),
+        ];
+        let scores = case_scores(&three_cases(), &results);
+
+        let base = &scores[0];
+        assert_eq!((base.judged, base.mean, base.change), (3, Some(4.0), None));
+        let base_scenarios = base.scenarios.iter().map(|score| (score.scenario.as_str(), score.mean)).collect::<Vec<_>>();
+        assert_eq!(base_scenarios, vec![("box", Some(5.0)), ("list", Some(2.0))]);
+
+        let better = &scores[1];
+        assert_eq!((better.judged, better.mean), (2, Some(6.0)));
+        assert_eq!(better.change, Some(50.0));
+        assert_eq!(better.scenarios[1].mean, Some(4.0));
+
+        let unjudged = &scores[2];
+        assert_eq!((unjudged.judged, unjudged.mean, unjudged.change), (0, None, None));
+        assert_eq!(unjudged.scenarios[1].mean, None);
+    }
+
+    #[test]
+    fn leaves_out_the_change_without_a_baseline_score() {
+        let zero = vec![("box".to_string(), vec![run("base", Some(0.0)), run("better", Some(5.0))])];
+        let unjudged = vec![("box".to_string(), vec![run("base", None), run("better", Some(5.0))])];
+        for results in [zero, unjudged] {
+            let scores = case_scores(&three_cases(), &results);
+            assert_eq!(scores[1].mean, Some(5.0));
+            assert_eq!(scores[1].change, None);
+        }
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            escape_html(r#"<b class="x">Tom & Jerry's</b>"#),
+            "&lt;b class=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/b&gt;"
+        );
+    }
+}
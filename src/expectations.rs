@@ -0,0 +1,336 @@
+use anyhow::Result;
+use image::{imageops, GrayImage};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A rectangle on the scenario's input.png, in its pixels
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+/// One thing a correct response has to do. Ink is what the response drew, not
+/// what was already on the page.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "check", rename_all = "snake_case")]
+pub enum Expectation {
+    /// Every listed region gets at least `min_pixels` of ink
+    InkInside {
+        regions: Vec<String>,
+        #[serde(default = "default_min_pixels")]
+        min_pixels: usize,
+    },
+    /// At most `max_pixels` of ink lands outside all of the listed regions
+    NoInkOutside {
+        regions: Vec<String>,
+        #[serde(default)]
+        max_pixels: usize,
+    },
+    /// At most `max_pixels` of ink lands in any of the listed regions
+    NoInkInside {
+        regions: Vec<String>,
+        #[serde(default)]
+        max_pixels: usize,
+    },
+    /// The text typed with draw_text matches this regular expression
+    TextMatches { pattern: String },
+    /// The response called this tool
+    ToolCalled { tool: String },
+}
+
+fn default_min_pixels() -> usize {
+    50
+}
+
+/// The outcome of one expectation for one run
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CheckResult {
+    pub check: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// What the response did, as far as the checks are concerned
+pub struct Response<'a> {
+    /// The rendered drawing (result.png), if anything was drawn
+    pub bitmap: Option<&'a Path>,
+    /// Everything typed with draw_text
+    pub text: &'a str,
+    /// Names of the tools called
+    pub tools: &'a [String],
+}
+
+/// A scenario's `expect.toml`: named regions of the input and the checks a correct answer passes
+#[derive(Deserialize, Debug, Clone)]
+pub struct Expectations {
+    #[serde(default)]
+    pub regions: HashMap<String, Region>,
+    pub expect: Vec<Expectation>,
+}
+
+impl Expectations {
+    /// Load `expect.toml` from a scenario directory, if it has one
+    pub fn load(scenario_dir: &Path) -> Result<Option<Self>> {
+        let path = scenario_dir.join("expect.toml");
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)?;
+        let expectations: Self = toml::from_str(&content).map_err(|e| anyhow::anyhow!("Failed to parse {:?}: {}", path, e))?;
+
+        for expectation in &expectations.expect {
+            match expectation {
+                Expectation::InkInside { regions, .. } | Expectation::NoInkOutside { regions, .. } | Expectation::NoInkInside { regions, .. } => {
+                    if let Some(missing) = regions.iter().find(|name| !expectations.regions.contains_key(*name)) {
+                        return Err(anyhow::anyhow!("{:?} refers to unknown region '{}'", path, missing));
+                    }
+                }
+                Expectation::TextMatches { pattern } => {
+                    Regex::new(pattern).map_err(|e| anyhow::anyhow!("{:?} has an invalid pattern '{}': {}", path, pattern, e))?;
+                }
+                Expectation::ToolCalled { .. } => {}
+            }
+        }
+        Ok(Some(expectations))
+    }
+
+    /// Run every check against a response to the scenario's `input`
+    pub fn check(&self, input: &Path, response: &Response) -> Result<Vec<CheckResult>> {
+        let ink = match response.bitmap {
+            Some(bitmap) => Some(load_ink(input, bitmap)?),
+            None => None,
+        };
+
+        Ok(self
+            .expect
+            .iter()
+            .map(|expectation| match expectation {
+                Expectation::InkInside { regions, min_pixels } => {
+                    let counts = regions
+                        .iter()
+                        .map(|name| (name, self.ink_in(&ink, |x, y| self.regions[name].contains(x, y))))
+                        .collect::<Vec<_>>();
+                    CheckResult {
+                        check: format!("ink inside {}", regions.join(", ")),
+                        passed: counts.iter().all(|(_, count)| *count >= *min_pixels),
+                        detail: counts
+                            .iter()
+                            .map(|(name, count)| format!("{}: {} px", name, count))
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                    }
+                }
+                Expectation::NoInkOutside { regions, max_pixels } => {
+                    let count = self.ink_in(&ink, |x, y| !regions.iter().any(|name| self.regions[name].contains(x, y)));
+                    // A response that drew nothing hasn't kept its drawing anywhere
+                    CheckResult {
+                        check: format!("no ink outside {}", regions.join(", ")),
+                        passed: ink.is_some() && count <= *max_pixels,
+                        detail: if ink.is_some() {
+                            format!("{} px outside", count)
+                        } else {
+                            "nothing drawn".to_string()
+                        },
+                    }
+                }
+                Expectation::NoInkInside { regions, max_pixels } => {
+                    let count = self.ink_in(&ink, |x, y| regions.iter().any(|name| self.regions[name].contains(x, y)));
+                    CheckResult {
+                        check: format!("no ink inside {}", regions.join(", ")),
+                        passed: count <= *max_pixels,
+                        detail: format!("{} px inside", count),
+                    }
+                }
+                Expectation::TextMatches { pattern } => CheckResult {
+                    check: format!("text matches /{}/", pattern),
+                    // Validated when loading
+                    passed: Regex::new(pattern).is_ok_and(|regex| regex.is_match(response.text)),
+                    detail: format!("{:?}", response.text.chars().take(80).collect::<String>()),
+                },
+                Expectation::ToolCalled { tool } => CheckResult {
+                    check: format!("called {}", tool),
+                    passed: response.tools.contains(tool),
+                    detail: if response.tools.is_empty() {
+                        "no tools called".to_string()
+                    } else {
+                        response.tools.join(", ")
+                    },
+                },
+            })
+            .collect())
+    }
+
+    /// Count the ink pixels where `inside` holds
+    fn ink_in(&self, ink: &Option<GrayImage>, inside: impl Fn(u32, u32) -> bool) -> usize {
+        ink.as_ref()
+            .map_or(0, |ink| ink.enumerate_pixels().filter(|(x, y, pixel)| pixel[0] < 128 && inside(*x, *y)).count())
+    }
+}
+
+/// The rendered response, scaled to the input's pixels so regions line up
+fn load_ink(input: &Path, bitmap: &Path) -> Result<GrayImage> {
+    let (width, height) = image::image_dimensions(input)?;
+    let ink = image::open(bitmap)?.to_luma8();
+    if ink.dimensions() == (width, height) {
+        return Ok(ink);
+    }
+    Ok(imageops::resize(&ink, width, height, imageops::FilterType::Nearest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+    use std::path::PathBuf;
+
+    const EXPECT: &str = r#"
+        [regions]
+        box = { x = 10, y = 10, width = 20, height = 20 }
+        label = { x = 50, y = 0, width = 10, height = 10 }
+
+        [[expect]]
+        check = "ink_inside"
+        regions = ["box"]
+        min_pixels = 50
+
+        [[expect]]
+        check = "no_ink_outside"
+        regions = ["box"]
+
+        [[expect]]
+        check = "tool_called"
+        tool = "draw_svg"
+    "#;
+
+    /// A scratch scenario directory holding `files`, each a name and its contents
+    fn scenario(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ghostwriter-expect-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            std::fs::write(dir.join(file), contents).unwrap();
+        }
+        dir
+    }
+
+    /// A white page of this size with black rectangles, each x, y, width, height
+    fn save_page(path: &Path, width: u32, height: u32, rects: &[(u32, u32, u32, u32)]) {
+        let mut image = GrayImage::from_pixel(width, height, Luma([255]));
+        for &(x, y, w, h) in rects {
+            for py in y..y + h {
+                for px in x..x + w {
+                    image.put_pixel(px, py, Luma([0]));
+                }
+            }
+        }
+        image.save(path).unwrap();
+    }
+
+    fn passed(results: &[CheckResult]) -> Vec<bool> {
+        results.iter().map(|result| result.passed).collect()
+    }
+
+    #[test]
+    fn regions_contain_their_own_pixels() {
+        let region = Region {
+            x: 10,
+            y: 20,
+            width: 5,
+            height: 5,
+        };
+        assert!(region.contains(10, 20));
+        assert!(region.contains(14, 24));
+        assert!(!region.contains(15, 24));
+        assert!(!region.contains(14, 25));
+        assert!(!region.contains(9, 22));
+    }
+
+    #[test]
+    fn loads_only_known_regions_and_valid_patterns() {
+        let dir = scenario("load", &[("expect.toml", EXPECT.as_bytes())]);
+        let loaded = Expectations::load(&dir).unwrap().unwrap();
+        assert_eq!(loaded.expect.len(), 3);
+
+        std::fs::write(dir.join("expect.toml"), "[[expect]]\ncheck = \"ink_inside\"\nregions = [\"nowhere\"]\n").unwrap();
+        let unknown = Expectations::load(&dir).unwrap_err().to_string();
+        std::fs::write(dir.join("expect.toml"), "[[expect]]\ncheck = \"text_matches\"\npattern = \"(unclosed\"\n").unwrap();
+        let bad_pattern = Expectations::load(&dir).unwrap_err().to_string();
+        std::fs::remove_file(dir.join("expect.toml")).unwrap();
+        let missing = Expectations::load(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert!(unknown.contains("unknown region 'nowhere'"), "{}", unknown);
+        assert!(bad_pattern.contains("invalid pattern '(unclosed'"), "{}", bad_pattern);
+        assert!(missing.is_none());
+    }
+
+    #[test]
+    fn counts_ink_on_a_resized_drawing() {
+        let dir = scenario("resized", &[]);
+        save_page(&dir.join("input.png"), 100, 100, &[]);
+        // Twice the input's size, so the 20 pixel square inside the box becomes 10 pixels after scaling
+        save_page(&dir.join("result.png"), 200, 200, &[(30, 30, 20, 20), (120, 120, 10, 10)]);
+        let expectations: Expectations = toml::from_str(EXPECT).unwrap();
+        let tools = vec!["draw_svg".to_string()];
+        let response = Response {
+            bitmap: Some(&dir.join("result.png")),
+            text: "",
+            tools: &tools,
+        };
+        let results = expectations.check(&dir.join("input.png"), &response).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(results[0].detail, "box: 100 px");
+        assert_eq!(results[1].detail, "25 px outside");
+        assert_eq!(passed(&results), vec![true, false, true]);
+    }
+
+    #[test]
+    fn nothing_drawn_fails_the_ink_checks() {
+        let dir = scenario("blank", &[]);
+        save_page(&dir.join("input.png"), 100, 100, &[]);
+        let expectations: Expectations = toml::from_str(EXPECT).unwrap();
+        let response = Response {
+            bitmap: None,
+            text: "",
+            tools: &[],
+        };
+        let results = expectations.check(&dir.join("input.png"), &response).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(passed(&results), vec![false, false, false]);
+        assert_eq!(results[1].detail, "nothing drawn");
+        assert_eq!(results[2].detail, "no tools called");
+    }
+
+    #[test]
+    fn checks_the_tools_called() {
+        let expectations: Expectations = toml::from_str(
+            r#"
+            [[expect]]
+            check = "tool_called"
+            tool = "draw_text"
+            "#,
+        )
+        .unwrap();
+        let check = |tools: &[String]| {
+            let response = Response { bitmap: None, text: "", tools };
+            expectations.check(Path::new("unused.png"), &response).unwrap().remove(0)
+        };
+
+        let called = check(&["draw_svg".to_string(), "draw_text".to_string()]);
+        assert!(called.passed);
+        assert_eq!(called.detail, "draw_svg, draw_text");
+        assert!(!check(&["draw_svg".to_string()]).passed);
+    }
+}
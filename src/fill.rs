@@ -0,0 +1,246 @@
+use anyhow::Result;
+use image::{GrayImage, Luma};
+use imageproc::contours::find_contours;
+use imageproc::distance_transform::Norm;
+use imageproc::morphology::erode;
+use log::debug;
+
+use crate::plotter::{order_polylines, Polyline};
+
+/// How filled areas of a rasterized SVG are turned into pen strokes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FillMode {
+    /// One stroke per run of pixels in each row (solid, but slow)
+    Scanline,
+    /// Only trace the edges of each shape
+    Outline,
+    /// Edges plus diagonal lines through the inside
+    Hatch,
+    /// Edges plus diagonal lines in both directions
+    CrossHatch,
+    /// Concentric contours shrinking towards the middle of each shape
+    Spiral,
+}
+
+impl FillMode {
+    pub fn from_string(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "scanline" => Ok(FillMode::Scanline),
+            "outline" => Ok(FillMode::Outline),
+            "hatch" => Ok(FillMode::Hatch),
+            "crosshatch" | "cross-hatch" => Ok(FillMode::CrossHatch),
+            "spiral" => Ok(FillMode::Spiral),
+            _ => Err(anyhow::anyhow!("Invalid fill mode: {}. Use scanline, outline, hatch, crosshatch, or spiral", s)),
+        }
+    }
+}
+
+/// Compute the strokes to draw a bitmap with the given fill mode. `spacing` is
+/// the distance in pixels between hatch lines or spiral rings.
+pub fn fill_strokes(bitmap: &[Vec<bool>], mode: FillMode, spacing: u32) -> Vec<Polyline> {
+    let image = bitmap_to_image(bitmap);
+    let spacing = spacing.max(1) as i32;
+
+    let mut strokes = match mode {
+        FillMode::Scanline => scanlines(bitmap),
+        FillMode::Outline => contours(&image),
+        FillMode::Hatch => {
+            let mut strokes = contours(&image);
+            strokes.extend(diagonals(bitmap, spacing, false));
+            strokes
+        }
+        FillMode::CrossHatch => {
+            let mut strokes = contours(&image);
+            strokes.extend(diagonals(bitmap, spacing, false));
+            strokes.extend(diagonals(bitmap, spacing, true));
+            strokes
+        }
+        FillMode::Spiral => rings(&image, spacing),
+    };
+    strokes.retain(|stroke| !stroke.is_empty());
+    debug!("{:?} fill produced {} strokes", mode, strokes.len());
+
+    order_polylines(strokes)
+}
+
+fn bitmap_to_image(bitmap: &[Vec<bool>]) -> GrayImage {
+    let height = bitmap.len() as u32;
+    let width = bitmap.first().map_or(0, |row| row.len()) as u32;
+    GrayImage::from_fn(width, height, |x, y| Luma([if bitmap[y as usize][x as usize] { 255 } else { 0 }]))
+}
+
+fn scanlines(bitmap: &[Vec<bool>]) -> Vec<Polyline> {
+    let mut strokes = Vec::new();
+    for (y, row) in bitmap.iter().enumerate() {
+        let mut run_start = None;
+        for x in 0..=row.len() {
+            let pixel = x < row.len() && row[x];
+            match (pixel, run_start) {
+                (true, None) => run_start = Some(x),
+                (false, Some(start)) => {
+                    strokes.push(vec![(start as i32, y as i32), (x as i32 - 1, y as i32)]);
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    strokes
+}
+
+fn contours(image: &GrayImage) -> Vec<Polyline> {
+    find_contours::<i32>(image)
+        .into_iter()
+        .map(|contour| {
+            let mut polyline: Polyline = contour.points.iter().map(|p| (p.x, p.y)).collect();
+            // Close the loop
+            if let Some(&first) = polyline.first() {
+                polyline.push(first);
+            }
+            polyline
+        })
+        .collect()
+}
+
+/// Runs of set pixels along 45 degree lines `spacing` pixels apart. The lines
+/// go down-right, or down-left when `mirrored`.
+fn diagonals(bitmap: &[Vec<bool>], spacing: i32, mirrored: bool) -> Vec<Polyline> {
+    let height = bitmap.len() as i32;
+    let width = bitmap.first().map_or(0, |row| row.len()) as i32;
+    let is_set = |x: i32, y: i32| x >= 0 && x < width && y >= 0 && y < height && bitmap[y as usize][x as usize];
+
+    let mut strokes = Vec::new();
+    let mut offset = -height;
+    while offset < width + height {
+        let mut run: Polyline = Vec::new();
+        for y in 0..height {
+            let x = if mirrored { offset - y } else { offset + y };
+            if is_set(x, y) {
+                run.push((x, y));
+            } else if !run.is_empty() {
+                strokes.push(vec![run[0], run[run.len() - 1]]);
+                run.clear();
+            }
+        }
+        if !run.is_empty() {
+            strokes.push(vec![run[0], run[run.len() - 1]]);
+        }
+        offset += spacing;
+    }
+    strokes
+}
+
+/// Trace the outline, shrink the shapes by `spacing`, and repeat until nothing is left
+fn rings(image: &GrayImage, spacing: i32) -> Vec<Polyline> {
+    let mut strokes = Vec::new();
+    let mut current = image.clone();
+    let step = spacing.clamp(1, u8::MAX as i32) as u8;
+
+    while current.pixels().any(|p| p[0] > 0) {
+        strokes.extend(contours(&current));
+        current = erode(&current, Norm::LInf, step);
+    }
+    strokes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 40 by 30 bitmap with a filled 20 pixel square at (10, 5)
+    fn square() -> Vec<Vec<bool>> {
+        (0..30)
+            .map(|y| (0..40).map(|x| (10..30).contains(&x) && (5..25).contains(&y)).collect())
+            .collect()
+    }
+
+    /// Every pixel a stroke passes over, stepping along each straight piece
+    fn pixels(stroke: &Polyline) -> Vec<(i32, i32)> {
+        let mut pixels = vec![stroke[0]];
+        for pair in stroke.windows(2) {
+            let ((x1, y1), (x2, y2)) = (pair[0], pair[1]);
+            let steps = (x2 - x1).abs().max((y2 - y1).abs()).max(1);
+            pixels.extend((1..=steps).map(|i| (x1 + (x2 - x1) * i / steps, y1 + (y2 - y1) * i / steps)));
+        }
+        pixels
+    }
+
+    fn assert_inside(bitmap: &[Vec<bool>], mode: FillMode) -> Vec<Polyline> {
+        let strokes = fill_strokes(bitmap, mode, 4);
+        assert!(!strokes.is_empty(), "{:?} drew nothing", mode);
+        for stroke in &strokes {
+            for (x, y) in pixels(stroke) {
+                assert!(bitmap[y as usize][x as usize], "{:?} drew outside the shape at ({}, {})", mode, x, y);
+            }
+        }
+        strokes
+    }
+
+    #[test]
+    fn every_fill_stays_inside_the_shape() {
+        let bitmap = square();
+        for mode in [FillMode::Scanline, FillMode::Outline, FillMode::Hatch, FillMode::CrossHatch, FillMode::Spiral] {
+            assert_inside(&bitmap, mode);
+        }
+    }
+
+    #[test]
+    fn scanlines_cover_each_row_once() {
+        let mut rows = assert_inside(&square(), FillMode::Scanline);
+        // Every other row is drawn backwards, to keep the pen moving
+        for row in &mut rows {
+            row.sort();
+        }
+        rows.sort();
+        let expected = (5..25).map(|y| vec![(10, y), (29, y)]).collect::<Vec<_>>();
+        assert_eq!(rows, expected);
+    }
+
+    #[test]
+    fn outlines_trace_only_the_edge() {
+        let bitmap = square();
+        let on_edge = |(x, y): (i32, i32)| x == 10 || x == 29 || y == 5 || y == 24;
+        for stroke in assert_inside(&bitmap, FillMode::Outline) {
+            assert!(pixels(&stroke).into_iter().all(on_edge), "{:?}", stroke);
+        }
+    }
+
+    #[test]
+    fn hatch_lines_are_spacing_apart() {
+        let bitmap = square();
+        for (mirrored, offset) in [(false, (|(x, y): (i32, i32)| x - y) as fn((i32, i32)) -> i32), (true, |(x, y)| x + y)] {
+            let strokes = diagonals(&bitmap, 4, mirrored);
+            // Each stroke stays on one diagonal
+            assert!(strokes.iter().all(|stroke| offset(stroke[0]) == offset(stroke[1])));
+            let mut offsets = strokes.iter().map(|stroke| offset(stroke[0])).collect::<Vec<_>>();
+            offsets.sort();
+            offsets.dedup();
+            assert_eq!(offsets.len(), strokes.len(), "one stroke per diagonal through a convex shape");
+            assert!(offsets.windows(2).all(|pair| pair[1] - pair[0] == 4), "{:?}", offsets);
+        }
+    }
+
+    #[test]
+    fn crosshatch_adds_the_mirrored_lines() {
+        let bitmap = square();
+        let hatch = fill_strokes(&bitmap, FillMode::Hatch, 4).len();
+        let crosshatch = fill_strokes(&bitmap, FillMode::CrossHatch, 4).len();
+        assert_eq!(crosshatch, hatch + diagonals(&bitmap, 4, true).len());
+    }
+
+    #[test]
+    fn spiral_rings_shrink_by_the_spacing() {
+        // A 20 pixel square loses 8 pixels across per ring at a spacing of 4: 20, 12 and 4 pixels wide
+        let rings = assert_inside(&square(), FillMode::Spiral);
+        assert_eq!(rings.len(), 3);
+        let mut widths = rings
+            .iter()
+            .map(|ring| {
+                let xs = ring.iter().map(|(x, _)| *x);
+                xs.clone().max().unwrap() - xs.min().unwrap() + 1
+            })
+            .collect::<Vec<_>>();
+        widths.sort();
+        assert_eq!(widths, vec![4, 12, 20]);
+    }
+}
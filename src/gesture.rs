@@ -0,0 +1,426 @@
+use anyhow::Result;
+use log::debug;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use crate::touch::TriggerArea;
+
+// Everything here is in virtual screen pixels (768x1024)
+const VIRTUAL_WIDTH: i32 = 768;
+const VIRTUAL_HEIGHT: i32 = 1024;
+
+/// A tap can wander this far and still be a tap
+const TAP_TRAVEL: i32 = 30;
+const TAP_TIME: Duration = Duration::from_millis(300);
+const LONG_PRESS_TIME: Duration = Duration::from_millis(800);
+/// Longest pause between the two taps of a double tap
+const DOUBLE_TAP_TIME: Duration = Duration::from_millis(400);
+/// A swipe has to start this close to the edge
+const EDGE_SIZE: i32 = 40;
+const SWIPE_DISTANCE: i32 = 150;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Gesture {
+    CornerTap,
+    CornerDoubleTap,
+    TwoFingerTap,
+    ThreeFingerTap,
+    LongPress,
+    SwipeFromLeft,
+    SwipeFromRight,
+    SwipeFromTop,
+    SwipeFromBottom,
+    /// The pen held over a trigger zone without touching the screen
+    PenHover,
+    /// A circle drawn with the pen in a trigger zone
+    PenCircle,
+    /// No writing for --idle-trigger seconds after writing something
+    Idle,
+}
+
+impl Gesture {
+    const ALL: [Gesture; 12] = [
+        Gesture::CornerTap,
+        Gesture::CornerDoubleTap,
+        Gesture::TwoFingerTap,
+        Gesture::ThreeFingerTap,
+        Gesture::LongPress,
+        Gesture::SwipeFromLeft,
+        Gesture::SwipeFromRight,
+        Gesture::SwipeFromTop,
+        Gesture::SwipeFromBottom,
+        Gesture::PenHover,
+        Gesture::PenCircle,
+        Gesture::Idle,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Gesture::CornerTap => "corner-tap",
+            Gesture::CornerDoubleTap => "corner-double-tap",
+            Gesture::TwoFingerTap => "two-finger-tap",
+            Gesture::ThreeFingerTap => "three-finger-tap",
+            Gesture::LongPress => "long-press",
+            Gesture::SwipeFromLeft => "swipe-from-left",
+            Gesture::SwipeFromRight => "swipe-from-right",
+            Gesture::SwipeFromTop => "swipe-from-top",
+            Gesture::SwipeFromBottom => "swipe-from-bottom",
+            Gesture::PenHover => "pen-hover",
+            Gesture::PenCircle => "pen-circle",
+            Gesture::Idle => "idle",
+        }
+    }
+
+    pub fn from_string(s: &str) -> Result<Self> {
+        Self::ALL.into_iter().find(|gesture| gesture.name() == s.to_lowercase()).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Invalid gesture: {}. Use {}",
+                s,
+                Self::ALL.iter().map(Gesture::name).collect::<Vec<_>>().join(", ")
+            )
+        })
+    }
+}
+
+/// A recognized gesture and the trigger zone it belongs to. Corner taps and pen
+/// gestures belong to the zone they happened in; every other gesture belongs to
+/// the main zone, 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trigger {
+    pub gesture: Gesture,
+    pub zone: usize,
+}
+
+/// What a gesture makes ghostwriter do
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GestureAction {
+    /// Respond to the page, continuing the conversation only when --conversation is on and the page is the same
+    Prompt,
+    /// Respond to the page in a fresh conversation
+    NewPrompt,
+    /// Respond to the page as the next turn of the conversation
+    Continue,
+    /// Erase what the last response put on the page
+    Undo,
+    /// Stop the request that's running
+    Cancel,
+    /// Ignore the gesture
+    None,
+}
+
+impl GestureAction {
+    pub fn from_string(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "prompt" => Ok(GestureAction::Prompt),
+            "new-prompt" => Ok(GestureAction::NewPrompt),
+            "continue" => Ok(GestureAction::Continue),
+            "undo" => Ok(GestureAction::Undo),
+            "cancel" => Ok(GestureAction::Cancel),
+            "none" => Ok(GestureAction::None),
+            _ => Err(anyhow::anyhow!(
+                "Invalid gesture action: {}. Use prompt, new-prompt, continue, undo, cancel, or none",
+                s
+            )),
+        }
+    }
+
+    /// Whether this action asks the model for a response
+    pub fn prompts(&self) -> bool {
+        matches!(self, GestureAction::Prompt | GestureAction::NewPrompt | GestureAction::Continue)
+    }
+}
+
+/// Parse the `[gestures]` config table, leaving out the ones set to "none"
+pub fn parse_gesture_actions(gestures: &HashMap<String, String>) -> Result<HashMap<Gesture, GestureAction>> {
+    let mut actions = HashMap::new();
+    for (gesture, action) in gestures {
+        let action = GestureAction::from_string(action)?;
+        if action != GestureAction::None {
+            actions.insert(Gesture::from_string(gesture)?, action);
+        }
+    }
+    Ok(actions)
+}
+
+struct Contact {
+    start: (i32, i32),
+    position: (i32, i32),
+}
+
+/// Turns finger contacts into gestures. A gesture is recognized once every finger has lifted.
+pub struct GestureRecognizer {
+    /// Where corner taps count, the main trigger corner first
+    zones: Vec<TriggerArea>,
+    wait_for_double_tap: bool,
+    contacts: HashMap<usize, Contact>,
+    /// When the first finger of the current gesture came down
+    started: Option<SystemTime>,
+    /// Most fingers down at once during the current gesture
+    fingers: usize,
+    /// Furthest any finger has moved during the current gesture
+    travel: i32,
+    /// A corner tap, and its zone, that might still become a double tap
+    pending_corner_tap: Option<(SystemTime, usize)>,
+}
+
+impl GestureRecognizer {
+    /// Corner taps only wait to see whether a second tap follows when `wait_for_double_tap` is set,
+    /// so a plain corner tap responds right away unless double taps are in use.
+    pub fn new(zones: Vec<TriggerArea>, wait_for_double_tap: bool) -> Self {
+        Self {
+            zones,
+            wait_for_double_tap,
+            contacts: HashMap::new(),
+            started: None,
+            fingers: 0,
+            travel: 0,
+            pending_corner_tap: None,
+        }
+    }
+
+    pub fn touch_down(&mut self, slot: usize, position: (i32, i32), time: SystemTime) {
+        if self.contacts.is_empty() {
+            self.started = Some(time);
+            self.fingers = 0;
+            self.travel = 0;
+        }
+        self.contacts.insert(slot, Contact { start: position, position });
+        self.fingers = self.fingers.max(self.contacts.len());
+    }
+
+    pub fn touch_move(&mut self, slot: usize, position: (i32, i32)) {
+        if let Some(contact) = self.contacts.get_mut(&slot) {
+            contact.position = position;
+            self.travel = self.travel.max(distance(contact.start, position));
+        }
+    }
+
+    /// A finger lifted; returns the gesture if it was the last one
+    pub fn touch_up(&mut self, slot: usize, time: SystemTime) -> Option<Trigger> {
+        let contact = self.contacts.remove(&slot)?;
+        if !self.contacts.is_empty() {
+            return None;
+        }
+        let duration = self.started.take().and_then(|started| time.duration_since(started).ok()).unwrap_or_default();
+        debug!(
+            "Touch ended: {} finger(s), {:?}, from {:?} to {:?}, travelled {}",
+            self.fingers, duration, contact.start, contact.position, self.travel
+        );
+
+        let is_tap = self.travel <= TAP_TRAVEL && duration <= TAP_TIME;
+        let main = |gesture| Some(Trigger { gesture, zone: 0 });
+        match self.fingers {
+            2 if is_tap => main(Gesture::TwoFingerTap),
+            3 if is_tap => main(Gesture::ThreeFingerTap),
+            1 => {
+                if let Some(swipe) = edge_swipe(contact.start, contact.position) {
+                    return main(swipe);
+                }
+                if self.travel <= TAP_TRAVEL && duration >= LONG_PRESS_TIME {
+                    return main(Gesture::LongPress);
+                }
+                // Any other release in a zone is a corner tap, however long it took or far it moved
+                let zone = self.zones.iter().position(|area| area.contains(contact.position))?;
+                match self.pending_corner_tap.take() {
+                    Some((previous, previous_zone)) if previous_zone == zone && time.duration_since(previous).is_ok_and(|pause| pause <= DOUBLE_TAP_TIME) => {
+                        Some(Trigger {
+                            gesture: Gesture::CornerDoubleTap,
+                            zone,
+                        })
+                    }
+                    // A tap in another zone, or too late to pair up, settles the waiting tap as a single one
+                    Some((_, previous_zone)) => {
+                        self.pending_corner_tap = Some((time, zone));
+                        Some(Trigger {
+                            gesture: Gesture::CornerTap,
+                            zone: previous_zone,
+                        })
+                    }
+                    None if self.wait_for_double_tap => {
+                        self.pending_corner_tap = Some((time, zone));
+                        None
+                    }
+                    _ => Some(Trigger {
+                        gesture: Gesture::CornerTap,
+                        zone,
+                    }),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether a corner tap is waiting to see if it becomes a double tap
+    pub fn is_waiting(&self) -> bool {
+        self.pending_corner_tap.is_some()
+    }
+
+    /// Give up on a double tap once the second tap is too late, returning the single tap
+    pub fn expire(&mut self, now: SystemTime) -> Option<Trigger> {
+        let (pending, zone) = self.pending_corner_tap?;
+        if self.contacts.is_empty() && now.duration_since(pending).is_ok_and(|pause| pause > DOUBLE_TAP_TIME) {
+            self.pending_corner_tap = None;
+            return Some(Trigger {
+                gesture: Gesture::CornerTap,
+                zone,
+            });
+        }
+        None
+    }
+}
+
+fn distance((x1, y1): (i32, i32), (x2, y2): (i32, i32)) -> i32 {
+    (((x2 - x1).pow(2) + (y2 - y1).pow(2)) as f32).sqrt() as i32
+}
+
+/// A single finger that started at an edge and moved mostly straight away from it
+fn edge_swipe((x1, y1): (i32, i32), (x2, y2): (i32, i32)) -> Option<Gesture> {
+    let (dx, dy) = (x2 - x1, y2 - y1);
+    if dx.abs() > dy.abs() {
+        if x1 < EDGE_SIZE && dx >= SWIPE_DISTANCE {
+            return Some(Gesture::SwipeFromLeft);
+        }
+        if x1 > VIRTUAL_WIDTH - EDGE_SIZE && -dx >= SWIPE_DISTANCE {
+            return Some(Gesture::SwipeFromRight);
+        }
+    } else {
+        if y1 < EDGE_SIZE && dy >= SWIPE_DISTANCE {
+            return Some(Gesture::SwipeFromTop);
+        }
+        if y1 > VIRTUAL_HEIGHT - EDGE_SIZE && -dy >= SWIPE_DISTANCE {
+            return Some(Gesture::SwipeFromBottom);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::touch::TriggerCorner;
+
+    const CORNER: (i32, i32) = (740, 20);
+
+    fn recognizer(wait_for_double_tap: bool) -> GestureRecognizer {
+        let zones = vec![
+            TriggerArea::Corner(TriggerCorner::UpperRight),
+            TriggerArea::Rect {
+                x: 0,
+                y: 500,
+                width: 100,
+                height: 100,
+            },
+        ];
+        GestureRecognizer::new(zones, wait_for_double_tap)
+    }
+
+    fn at(ms: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    /// One finger down at `from`, moved to `to`, and lifted `duration_ms` later
+    fn stroke(recognizer: &mut GestureRecognizer, start_ms: u64, from: (i32, i32), to: (i32, i32), duration_ms: u64) -> Option<Trigger> {
+        recognizer.touch_down(0, from, at(start_ms));
+        recognizer.touch_move(0, to);
+        recognizer.touch_up(0, at(start_ms + duration_ms))
+    }
+
+    fn gesture(trigger: Option<Trigger>) -> Option<Gesture> {
+        trigger.map(|trigger| trigger.gesture)
+    }
+
+    #[test]
+    fn taps_in_a_zone() {
+        let mut recognizer = recognizer(false);
+        assert_eq!(
+            stroke(&mut recognizer, 0, CORNER, CORNER, 100),
+            Some(Trigger {
+                gesture: Gesture::CornerTap,
+                zone: 0
+            })
+        );
+        assert_eq!(
+            stroke(&mut recognizer, 1000, (50, 550), (55, 552), 100),
+            Some(Trigger {
+                gesture: Gesture::CornerTap,
+                zone: 1
+            })
+        );
+        assert_eq!(stroke(&mut recognizer, 2000, (400, 400), (400, 400), 100), None);
+    }
+
+    #[test]
+    fn double_taps_and_expires_a_single_tap() {
+        let mut recognizer = recognizer(true);
+        assert_eq!(stroke(&mut recognizer, 0, CORNER, CORNER, 100), None);
+        assert!(recognizer.is_waiting());
+        assert_eq!(gesture(stroke(&mut recognizer, 300, CORNER, CORNER, 100)), Some(Gesture::CornerDoubleTap));
+        assert!(!recognizer.is_waiting());
+
+        assert_eq!(stroke(&mut recognizer, 1000, CORNER, CORNER, 100), None);
+        assert_eq!(recognizer.expire(at(1300)), None);
+        assert_eq!(gesture(recognizer.expire(at(1600))), Some(Gesture::CornerTap));
+        assert!(!recognizer.is_waiting());
+    }
+
+    #[test]
+    fn taps_with_several_fingers() {
+        let mut recognizer = recognizer(false);
+        recognizer.touch_down(0, (300, 300), at(0));
+        recognizer.touch_down(1, (400, 300), at(20));
+        assert_eq!(recognizer.touch_up(0, at(120)), None);
+        assert_eq!(gesture(recognizer.touch_up(1, at(150))), Some(Gesture::TwoFingerTap));
+    }
+
+    #[test]
+    fn swipes_from_the_edges() {
+        let mut recognizer = recognizer(false);
+        assert_eq!(gesture(stroke(&mut recognizer, 0, (760, 500), (500, 520), 200)), Some(Gesture::SwipeFromRight));
+        assert_eq!(gesture(stroke(&mut recognizer, 1000, (10, 500), (300, 480), 200)), Some(Gesture::SwipeFromLeft));
+        assert_eq!(gesture(stroke(&mut recognizer, 2000, (400, 5), (390, 400), 200)), Some(Gesture::SwipeFromTop));
+        assert_eq!(
+            gesture(stroke(&mut recognizer, 3000, (400, 1020), (410, 700), 200)),
+            Some(Gesture::SwipeFromBottom)
+        );
+        // Too short, or not from an edge
+        assert_eq!(stroke(&mut recognizer, 4000, (760, 500), (680, 500), 200), None);
+        assert_eq!(stroke(&mut recognizer, 5000, (300, 500), (600, 500), 200), None);
+    }
+
+    #[test]
+    fn long_presses_in_place() {
+        let mut recognizer = recognizer(false);
+        assert_eq!(gesture(stroke(&mut recognizer, 0, (300, 300), (310, 305), 900)), Some(Gesture::LongPress));
+        // Held too long for a tap but too short for a long press
+        assert_eq!(stroke(&mut recognizer, 2000, (300, 300), (300, 300), 500), None);
+    }
+
+    #[test]
+    fn any_release_in_a_zone_is_a_tap() {
+        let mut recognizer = recognizer(false);
+        assert_eq!(gesture(stroke(&mut recognizer, 0, CORNER, CORNER, 500)), Some(Gesture::CornerTap));
+        assert_eq!(gesture(stroke(&mut recognizer, 1000, (700, 60), CORNER, 200)), Some(Gesture::CornerTap));
+        // Unless it was held long enough to be a long press
+        assert_eq!(gesture(stroke(&mut recognizer, 2000, CORNER, CORNER, 900)), Some(Gesture::LongPress));
+    }
+
+    #[test]
+    fn a_tap_in_another_zone_settles_the_waiting_tap() {
+        let mut recognizer = recognizer(true);
+        assert_eq!(stroke(&mut recognizer, 0, CORNER, CORNER, 100), None);
+        assert_eq!(
+            stroke(&mut recognizer, 200, (50, 550), (50, 550), 100),
+            Some(Trigger {
+                gesture: Gesture::CornerTap,
+                zone: 0
+            })
+        );
+        assert_eq!(
+            recognizer.expire(at(1000)),
+            Some(Trigger {
+                gesture: Gesture::CornerTap,
+                zone: 1
+            })
+        );
+    }
+}
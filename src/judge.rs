@@ -0,0 +1,118 @@
+use anyhow::Result;
+use base64::prelude::*;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_json::Value as json;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::config::FallbackModel;
+use crate::llm_engine::{create_engine, determine_engine_name, LLMEngine, ToolOutput};
+use crate::util::OptionMap;
+
+const JUDGE_PROMPT: &str = "You are grading the output of a handwriting assistant that lives in an e-ink notepad. \
+The image shows the page as the user left it, with everything the assistant drew added on top in red. \
+Judge the assistant's response against the rubric below, looking only at how well it follows the rubric. \
+Then call submit_score with a score from 0 (useless or wrong) to 10 (exactly right) and a short rationale.";
+
+/// A judge's verdict on one evaluation run
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Judgement {
+    pub score: f64,
+    pub rationale: String,
+    pub model: String,
+}
+
+/// Scores evaluation outputs against a scenario's rubric using another model
+pub struct Judge {
+    engine: Box<dyn LLMEngine>,
+    model: String,
+    verdict: Rc<RefCell<Option<(f64, String)>>>,
+}
+
+impl Judge {
+    pub fn new(target: &FallbackModel) -> Result<Self> {
+        let mut options = OptionMap::new();
+        options.insert("model".to_string(), target.model.clone());
+        if let Some(base_url) = &target.base_url {
+            options.insert("base_url".to_string(), base_url.clone());
+        }
+        if let Some(api_key) = &target.api_key {
+            options.insert("api_key".to_string(), api_key.clone());
+        }
+
+        let engine_name = determine_engine_name(&target.engine, &target.model)?;
+        let mut engine = create_engine(&engine_name, &options)?;
+
+        let verdict = Rc::new(RefCell::new(None));
+        let submitted = Rc::clone(&verdict);
+        engine.register_tool(
+            "submit_score",
+            json!({
+                "name": "submit_score",
+                "description": "Submit the grade for the assistant's response",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "score": {
+                            "type": "number",
+                            "description": "From 0 (useless or wrong) to 10 (exactly right)"
+                        },
+                        "rationale": {
+                            "type": "string",
+                            "description": "One or two sentences on why it got this score"
+                        }
+                    },
+                    "required": ["score", "rationale"]
+                },
+                // The verdict is all the judge is for, so its first submission ends the turn
+                "next_action": "end"
+            }),
+            Box::new(move |arguments: json| {
+                let Some(score) = arguments["score"].as_f64() else {
+                    return ToolOutput::Error("score must be a number from 0 to 10".to_string());
+                };
+                let mut verdict = submitted.borrow_mut();
+                if verdict.is_some() {
+                    return ToolOutput::Text("A score was already recorded".to_string());
+                }
+                let rationale = arguments["rationale"].as_str().unwrap_or_default().to_string();
+                *verdict = Some((score.clamp(0.0, 10.0), rationale));
+                ToolOutput::Text("Score recorded".to_string())
+            }),
+        );
+
+        Ok(Self {
+            engine,
+            model: target.model.clone(),
+            verdict,
+        })
+    }
+
+    /// Score one response. `image_png` is the page with the response drawn in,
+    /// `response` describes what the assistant did (the tool it called, text it typed).
+    pub fn judge(&mut self, image_png: &[u8], rubric: &str, response: &str) -> Result<Judgement> {
+        *self.verdict.borrow_mut() = None;
+        self.engine.clear_history();
+        self.engine.clear_content();
+        self.engine.add_image_content(&BASE64_STANDARD.encode(image_png));
+        self.engine.add_text_content(&format!(
+            "{}\n\n# Rubric\n\n{}\n\n# The assistant's response\n\n{}",
+            JUDGE_PROMPT, rubric, response
+        ));
+        self.engine.execute()?;
+
+        let (score, rationale) = self
+            .verdict
+            .borrow_mut()
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Judge {} did not submit a score", self.model))?;
+        debug!("Judged {}: {}", score, rationale);
+        Ok(Judgement {
+            score,
+            rationale,
+            model: self.model.clone(),
+        })
+    }
+}
@@ -269,6 +269,15 @@ impl Keyboard {
         Ok(())
     }
 
+    /// Backspace over text typed with string_to_keypresses
+    pub fn erase(&mut self, text: &str) -> Result<()> {
+        let typed = text.chars().filter(|c| self.key_map.contains_key(c)).count();
+        for _ in 0..typed {
+            self.string_to_keypresses("\x08")?;
+        }
+        Ok(())
+    }
+
     fn key_cmd(&mut self, button: &str, shift: bool) -> Result<()> {
         self.key_down(EvdevKey::KEY_LEFTCTRL)?;
         if shift {
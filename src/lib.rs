@@ -1,10 +1,21 @@
 pub mod config;
+pub mod crop;
 pub mod device;
 pub mod embedded_assets;
+pub mod eval;
+pub mod expectations;
+pub mod fill;
+pub mod gesture;
+pub mod judge;
 pub mod keyboard;
 pub mod llm_engine;
+pub mod mcp;
 pub mod pen;
+pub mod pen_trigger;
+pub mod plotter;
 pub mod screenshot;
 pub mod segmenter;
+pub mod tools;
 pub mod touch;
+pub mod usage;
 pub mod util;
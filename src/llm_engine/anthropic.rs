@@ -1,43 +1,28 @@
-use super::LLMEngine;
+use super::conversation::{Content, Message, Role};
+use super::error::EngineError;
+use super::http::read_json;
+use super::sse::read_events;
+use super::{record_exchange, EngineBackend, EngineState, Tool, ToolCall, ToolOutput, Usage};
 use crate::util::{option_or_env, option_or_env_fallback, OptionMap};
 use anyhow::Result;
 use log::debug;
 use serde_json::json;
 use serde_json::Value as json;
-
-pub struct Tool {
-    name: String,
-    definition: json,
-    callback: Option<Box<dyn FnMut(json)>>,
-}
+use std::io::Read;
+use std::time::Instant;
 
 pub struct Anthropic {
     model: String,
     api_key: String,
     base_url: String,
-    tools: Vec<Tool>,
-    content: Vec<json>,
     web_search: bool,
     thinking: bool,
     thinking_tokens: u32,
+    state: EngineState,
 }
 
 impl Anthropic {
-    pub fn add_content(&mut self, content: json) {
-        self.content.push(content);
-    }
-
-    fn anthropic_tool_definition(tool: &Tool) -> json {
-        json!({
-            "name": tool.definition["name"],
-            "description": tool.definition["description"],
-            "input_schema": tool.definition["parameters"],
-        })
-    }
-}
-
-impl LLMEngine for Anthropic {
-    fn new(options: &OptionMap) -> Self {
+    pub fn new(options: &OptionMap) -> Self {
         let api_key = option_or_env(options, "api_key", "ANTHROPIC_API_KEY");
         let base_url = option_or_env_fallback(options, "base_url", "ANTHROPIC_BASE_URL", "https://api.anthropic.com");
         let model = options.get("model").unwrap().to_string();
@@ -49,46 +34,171 @@ impl LLMEngine for Anthropic {
             model,
             base_url,
             api_key,
-            tools: Vec::new(),
-            content: Vec::new(),
             web_search,
             thinking,
             thinking_tokens,
+            state: EngineState::new(options),
         }
     }
 
-    fn register_tool(&mut self, name: &str, definition: json, callback: Box<dyn FnMut(json)>) {
-        self.tools.push(Tool {
-            name: name.to_string(),
-            definition,
-            callback: Some(callback),
-        });
+    fn anthropic_tool_definition(tool: &Tool) -> json {
+        json!({
+            "name": tool.definition["name"],
+            "description": tool.definition["description"],
+            "input_schema": tool.definition["parameters"],
+        })
     }
 
-    fn add_text_content(&mut self, text: &str) {
-        self.add_content(json!({
-            "type": "text",
-            "text": text,
-        }));
+    fn anthropic_content(content: &Content) -> json {
+        match content {
+            Content::Text(text) => json!({
+                "type": "text",
+                "text": text,
+            }),
+            Content::Image(base64_image) => json!({
+                "type": "image",
+                "source": {
+                    "type": "base64",
+                    "media_type": "image/png",
+                    "data": base64_image
+                }
+            }),
+            Content::Thinking { thinking, signature } => json!({
+                "type": "thinking",
+                "thinking": thinking,
+                "signature": signature,
+            }),
+            Content::ToolCall { id, name, arguments } => json!({
+                "type": "tool_use",
+                "id": id,
+                "name": name,
+                "input": arguments,
+            }),
+            Content::ToolResult { id, output, .. } => match output {
+                ToolOutput::Text(text) => json!({
+                    "type": "tool_result",
+                    "tool_use_id": id,
+                    "content": text,
+                }),
+                ToolOutput::Image(base64_image) => json!({
+                    "type": "tool_result",
+                    "tool_use_id": id,
+                    "content": [Self::anthropic_content(&Content::Image(base64_image.clone()))],
+                }),
+                ToolOutput::Error(error) => json!({
+                    "type": "tool_result",
+                    "tool_use_id": id,
+                    "content": error,
+                    "is_error": true,
+                }),
+            },
+        }
+    }
+
+    fn anthropic_message(message: &Message) -> json {
+        json!({
+            "role": match message.role {
+                Role::User => "user",
+                Role::Assistant => "assistant",
+            },
+            "content": message.content.iter().map(Self::anthropic_content).collect::<Vec<_>>(),
+        })
     }
 
-    fn add_image_content(&mut self, base64_image: &str) {
-        self.add_content(json!({
-            "type": "image",
-            "source": {
-                "type": "base64",
-                "media_type": "image/png",
-                "data": base64_image
+    /// Reassemble a streamed response into the same shape as a regular one
+    fn read_stream(&mut self, reader: impl Read) -> Result<json> {
+        let mut message = json!({ "content": [] });
+        let mut partial_inputs: Vec<String> = Vec::new();
+        let progress = &mut self.state.progress;
+
+        read_events(reader, &self.state.cancel, |_event, data| {
+            match data["type"].as_str().unwrap_or("") {
+                "message_start" => {
+                    message = data["message"].clone();
+                    message["content"] = json!([]);
+                }
+                "content_block_start" => {
+                    let index = data["index"].as_u64().unwrap_or(0) as usize;
+                    let content = message["content"]
+                        .as_array_mut()
+                        .ok_or_else(|| EngineError::MalformedResponse("content_block_start before message_start".to_string()))?;
+                    while content.len() <= index {
+                        content.push(json!(null));
+                        partial_inputs.push(String::new());
+                    }
+                    content[index] = data["content_block"].clone();
+                }
+                "content_block_delta" => {
+                    let index = data["index"].as_u64().unwrap_or(0) as usize;
+                    let delta = &data["delta"];
+                    let block = &mut message["content"][index];
+                    let (field, text) = match delta["type"].as_str().unwrap_or("") {
+                        "text_delta" => ("text", delta["text"].as_str().unwrap_or("")),
+                        "thinking_delta" => ("thinking", delta["thinking"].as_str().unwrap_or("")),
+                        "signature_delta" => {
+                            block["signature"] = delta["signature"].clone();
+                            return Ok(());
+                        }
+                        "input_json_delta" => {
+                            let partial_json = delta["partial_json"].as_str().unwrap_or("");
+                            if let Some(partial_input) = partial_inputs.get_mut(index) {
+                                partial_input.push_str(partial_json);
+                            }
+                            if let Some(progress) = progress {
+                                progress(partial_json);
+                            }
+                            return Ok(());
+                        }
+                        _ => return Ok(()),
+                    };
+                    let joined = format!("{}{}", block[field].as_str().unwrap_or(""), text);
+                    block[field] = json!(joined);
+                    if let Some(progress) = progress {
+                        progress(text);
+                    }
+                }
+                "content_block_stop" => {
+                    let index = data["index"].as_u64().unwrap_or(0) as usize;
+                    if let Some(partial_input) = partial_inputs.get(index).filter(|input| !input.is_empty()) {
+                        message["content"][index]["input"] = serde_json::from_str(partial_input)?;
+                    }
+                }
+                "message_delta" => {
+                    if let Some(delta) = data["delta"].as_object() {
+                        for (key, value) in delta {
+                            message[key] = value.clone();
+                        }
+                    }
+                    // Output counts arrive here, input counts came with message_start
+                    if let Some(usage) = data["usage"].as_object() {
+                        for (key, value) in usage {
+                            message["usage"][key] = value.clone();
+                        }
+                    }
+                }
+                "error" => {
+                    return Err(EngineError::from_api_error(&data["error"]).into());
+                }
+                _ => {}
             }
-        }));
+            Ok(())
+        })?;
+
+        Ok(message)
+    }
+}
+
+impl EngineBackend for Anthropic {
+    fn state(&self) -> &EngineState {
+        &self.state
     }
 
-    fn clear_content(&mut self) {
-        self.content.clear();
+    fn state_mut(&mut self) -> &mut EngineState {
+        &mut self.state
     }
 
-    fn execute(&mut self) -> Result<()> {
-        let mut tool_definitions = self.tools.iter().map(Self::anthropic_tool_definition).collect::<Vec<_>>();
+    fn send_request(&mut self) -> Result<json> {
+        let mut tool_definitions = self.state.tools.iter().map(Self::anthropic_tool_definition).collect::<Vec<_>>();
 
         // Add web search tool if enabled
         if self.web_search {
@@ -102,10 +212,7 @@ impl LLMEngine for Anthropic {
         let mut body = json!({
             "model": self.model,
             "max_tokens": 10000,
-            "messages": [{
-                "role": "user",
-                "content": self.content
-            }],
+            "messages": self.state.conversation.request_messages().iter().map(Self::anthropic_message).collect::<Vec<_>>(),
             "tools": tool_definitions,
             "tool_choice": {
                 "type": "auto",
@@ -121,57 +228,70 @@ impl LLMEngine for Anthropic {
             });
         }
 
+        if self.state.stream {
+            body["stream"] = json!(true);
+        }
+
         debug!("Request: {}", body);
 
-        let raw_response = ureq::post(&format!("{}/v1/messages", self.base_url))
-            .header("x-api-key", self.api_key.as_str())
-            .header("anthropic-version", "2023-06-01")
-            .header("Content-Type", "application/json")
-            .send_json(&body);
-
-        let mut response = match raw_response {
-            Ok(response) => response,
-            Err(err) => {
-                debug!("API Error: {}", err);
-                return Err(anyhow::anyhow!("API ERROR: {}", err));
-            }
-        };
+        let started = Instant::now();
+        let mut response = self.state.post(
+            &format!("{}/v1/messages", self.base_url),
+            &[
+                ("x-api-key", self.api_key.as_str()),
+                ("anthropic-version", "2023-06-01"),
+                ("Content-Type", "application/json"),
+            ],
+            &body,
+        )?;
 
-        // Read response body as string
-        let body_text = response.body_mut().read_to_string().unwrap();
-        let json: json = serde_json::from_str(&body_text).unwrap();
+        let json = if self.state.stream {
+            self.read_stream(response.body_mut().as_reader())?
+        } else {
+            read_json(&mut response)?
+        };
         debug!("Response: {}", json);
-        let content_array = &json["content"];
+        record_exchange(&mut self.state.exchange_callback, &body, &json, started);
+        Ok(json)
+    }
+
+    fn parse_response(&self, response: &json, _step: u32) -> Result<(Vec<Content>, Vec<ToolCall>)> {
+        let mut assistant_content = Vec::new();
+        let mut tool_calls = Vec::new();
 
         // Loop through all content entries
-        for content_item in content_array.as_array().unwrap_or(&Vec::new()) {
+        for content_item in response["content"].as_array().unwrap_or(&Vec::new()) {
             let content_type = content_item["type"].as_str().unwrap_or("");
 
             match content_type {
                 "tool_use" => {
-                    let function_name = content_item["name"].as_str().unwrap();
-                    let function_input = &content_item["input"];
-                    let tool = self.tools.iter_mut().find(|tool| tool.name == function_name);
-
-                    if let Some(tool) = tool {
-                        if let Some(callback) = &mut tool.callback {
-                            callback(function_input.clone());
-                            return Ok(());
-                        } else {
-                            return Err(anyhow::anyhow!("No callback registered for tool {}", function_name));
-                        }
-                    } else {
-                        return Err(anyhow::anyhow!("No tool registered with name {}", function_name));
-                    }
+                    let id = content_item["id"].as_str().unwrap_or_default().to_string();
+                    let function_name = content_item["name"].as_str().unwrap_or_default().to_string();
+                    let function_input = content_item["input"].clone();
+                    assistant_content.push(Content::ToolCall {
+                        id: id.clone(),
+                        name: function_name.clone(),
+                        arguments: function_input.clone(),
+                    });
+                    tool_calls.push(ToolCall {
+                        id,
+                        name: function_name,
+                        arguments: function_input,
+                    });
                 }
                 "thinking" => {
                     if let Some(thinking) = content_item.get("thinking") {
                         debug!("Thinking: {}", thinking);
+                        assistant_content.push(Content::Thinking {
+                            thinking: thinking.as_str().unwrap_or_default().to_string(),
+                            signature: content_item["signature"].as_str().unwrap_or_default().to_string(),
+                        });
                     }
                 }
                 "text" => {
                     if let Some(text) = content_item.get("text") {
                         debug!("Text: {}", text);
+                        assistant_content.push(Content::Text(text.as_str().unwrap_or_default().to_string()));
                     }
                 }
                 _ => {
@@ -180,6 +300,51 @@ impl LLMEngine for Anthropic {
             }
         }
 
-        Err(anyhow::anyhow!("No tool calls found in response"))
+        Ok((assistant_content, tool_calls))
+    }
+
+    fn parse_usage(&self, response: &json) -> Usage {
+        let usage = &response["usage"];
+        Usage {
+            input_tokens: usage["input_tokens"].as_u64().unwrap_or(0)
+                + usage["cache_creation_input_tokens"].as_u64().unwrap_or(0)
+                + usage["cache_read_input_tokens"].as_u64().unwrap_or(0),
+            // Thinking is billed as output, but not counted separately
+            output_tokens: usage["output_tokens"].as_u64().unwrap_or(0),
+            thinking_tokens: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm_engine::conversation::Conversation;
+
+    #[test]
+    fn serializes_the_history_as_messages() {
+        let mut conversation = Conversation::new();
+        conversation.add_content(Content::Text("Page".to_string()));
+        conversation.commit_user_turn();
+        conversation.add_assistant_turn(vec![Content::ToolCall {
+            id: "toolu_1".to_string(),
+            name: "draw_text".to_string(),
+            arguments: json!({ "text": "hi" }),
+        }]);
+        conversation.add_tool_result("toolu_1", "draw_text", ToolOutput::Error("Pen is busy".to_string()));
+        conversation.add_content(Content::Image("abc".to_string()));
+
+        let messages = conversation.request_messages().iter().map(Anthropic::anthropic_message).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                json!({ "role": "user", "content": [{ "type": "text", "text": "Page" }] }),
+                json!({ "role": "assistant", "content": [{ "type": "tool_use", "id": "toolu_1", "name": "draw_text", "input": { "text": "hi" } }] }),
+                json!({ "role": "user", "content": [
+                    { "type": "tool_result", "tool_use_id": "toolu_1", "content": "Pen is busy", "is_error": true },
+                    { "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": "abc" } },
+                ] }),
+            ]
+        );
     }
 }
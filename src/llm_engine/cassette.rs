@@ -0,0 +1,140 @@
+use super::error::EngineError;
+use crate::util::OptionMap;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as json;
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CassetteMode {
+    /// Call the provider and append every exchange to the cassette
+    Record,
+    /// Answer from the cassette and never touch the network
+    Replay,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CassetteEntry {
+    key: String,
+    url: String,
+    status: u16,
+    response: String,
+}
+
+/// A file of recorded provider responses, one JSON object per line, keyed by a
+/// hash of the request body. Replaying the same prompt and screenshot gives the
+/// same answer without a network connection or an API key.
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+}
+
+impl Cassette {
+    pub fn new(path: impl Into<PathBuf>, mode: CassetteMode) -> Self {
+        Self { path: path.into(), mode }
+    }
+
+    /// From the "cassette" and "cassette_mode" (record or replay, default replay) engine options
+    pub fn from_options(options: &OptionMap) -> Option<Self> {
+        let path = options.get("cassette")?;
+        let mode = match options.get("cassette_mode").map(String::as_str) {
+            Some("record") => CassetteMode::Record,
+            _ => CassetteMode::Replay,
+        };
+        Some(Self::new(path, mode))
+    }
+
+    pub fn is_replay(&self) -> bool {
+        self.mode == CassetteMode::Replay
+    }
+
+    /// FNV-1a hash of the URL and the serialized request body. The URL matters
+    /// because Google puts the model and whether it streams there, not in the body.
+    pub fn key(url: &str, body: &json) -> String {
+        let hash = format!("{} {}", without_query(url), body)
+            .bytes()
+            .fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+        format!("{:016x}", hash)
+    }
+
+    /// The recorded status and response body for this request. When a request was
+    /// recorded more than once the latest recording wins.
+    pub fn lookup(&self, url: &str, body: &json) -> Result<(u16, String), EngineError> {
+        let key = Self::key(url, body);
+        let contents = std::fs::read_to_string(&self.path).map_err(|e| EngineError::Cassette(format!("Failed to read cassette {:?}: {}", self.path, e)))?;
+
+        let entry = contents
+            .lines()
+            .rev()
+            .filter_map(|line| serde_json::from_str::<CassetteEntry>(line).ok())
+            .find(|entry| entry.key == key)
+            .ok_or_else(|| EngineError::Cassette(format!("No recorded response for request {} in cassette {:?}", key, self.path)))?;
+        debug!("Replaying request {} from cassette", key);
+        Ok((entry.status, entry.response))
+    }
+
+    /// Append an exchange. A cassette that can't be written shouldn't fail the request, so this only warns.
+    pub fn record(&self, url: &str, body: &json, status: u16, response: &str) {
+        let entry = CassetteEntry {
+            key: Self::key(url, body),
+            url: without_query(url).to_string(),
+            status,
+            response: response.to_string(),
+        };
+        debug!("Recording request {} to cassette", entry.key);
+
+        let result = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{}", serde_json::to_string(&entry).unwrap_or_default()));
+        if let Err(e) = result {
+            warn!("Failed to write cassette {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// Google puts the API key in the query, so it's left out of keys and recordings
+fn without_query(url: &str) -> &str {
+    url.split('?').next().unwrap_or(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn key_tells_google_models_and_streaming_apart() {
+        let body = json!({ "contents": [] });
+        let flash = Cassette::key("https://example.com/v1beta/models/gemini-2.5-flash:generateContent?key=a", &body);
+        let pro = Cassette::key("https://example.com/v1beta/models/gemini-2.5-pro:generateContent?key=a", &body);
+        let streamed = Cassette::key("https://example.com/v1beta/models/gemini-2.5-flash:streamGenerateContent?alt=sse&key=a", &body);
+        assert_ne!(flash, pro);
+        assert_ne!(flash, streamed);
+        // Only the API key differs
+        assert_eq!(
+            flash,
+            Cassette::key("https://example.com/v1beta/models/gemini-2.5-flash:generateContent?key=b", &body)
+        );
+    }
+
+    #[test]
+    fn recording_leaves_out_the_url_query() {
+        let path = std::env::temp_dir().join(format!("ghostwriter-cassette-{}.jsonl", std::process::id()));
+        let cassette = Cassette::new(&path, CassetteMode::Record);
+        let body = json!({ "contents": [] });
+        cassette.record("https://example.com/v1beta/models/gemini:generateContent?key=secret", &body, 200, "{}");
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        // Replay finds it again whatever API key the URL has
+        let replayed = Cassette::new(&path, CassetteMode::Replay).lookup("https://example.com/v1beta/models/gemini:generateContent?key=other", &body);
+        std::fs::remove_file(&path).ok();
+
+        assert!(!contents.contains("secret"));
+        assert!(contents.contains("\"url\":\"https://example.com/v1beta/models/gemini:generateContent\""));
+        let (status, response) = replayed.unwrap();
+        assert_eq!((status, response.as_str()), (200, "{}"));
+    }
+}
@@ -0,0 +1,263 @@
+use super::ToolOutput;
+use serde_json::Value as json;
+
+/// Stands in for an image dropped from the history
+const IMAGE_LEFT_OUT: &str = "[An earlier screenshot, left out to save space]";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone)]
+pub enum Content {
+    Text(String),
+    Image(String), // base64 encoded PNG
+    Thinking { thinking: String, signature: String },
+    ToolCall { id: String, name: String, arguments: json },
+    ToolResult { id: String, name: String, output: ToolOutput },
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub role: Role,
+    pub content: Vec<Content>,
+}
+
+/// Engine-neutral record of a dialogue with the model.
+///
+/// Content added for the next request is kept as a pending user turn and only
+/// becomes part of the history once the engine gets a successful response, so
+/// a failed request does not leave a dangling turn behind.
+#[derive(Debug, Clone, Default)]
+pub struct Conversation {
+    messages: Vec<Message>,
+    pending: Vec<Content>,
+}
+
+impl Conversation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_content(&mut self, content: Content) {
+        self.pending.push(content);
+    }
+
+    pub fn clear_pending(&mut self) {
+        self.pending.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.pending.clear();
+    }
+
+    /// The full message list for a request: the history plus the pending user turn.
+    /// Consecutive user content (like tool results followed by a new screenshot) is
+    /// merged into a single user message.
+    pub fn request_messages(&self) -> Vec<Message> {
+        let mut messages = self.messages.clone();
+        if !self.pending.is_empty() {
+            Self::push_merged(&mut messages, Role::User, self.pending.clone());
+        }
+        messages
+    }
+
+    /// Move the pending user turn into the history
+    pub fn commit_user_turn(&mut self) {
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            Self::push_merged(&mut self.messages, Role::User, pending);
+        }
+    }
+
+    pub fn add_assistant_turn(&mut self, content: Vec<Content>) {
+        if !content.is_empty() {
+            Self::push_merged(&mut self.messages, Role::Assistant, content);
+        }
+    }
+
+    pub fn add_tool_result(&mut self, id: &str, name: &str, output: ToolOutput) {
+        Self::push_merged(
+            &mut self.messages,
+            Role::User,
+            vec![Content::ToolResult {
+                id: id.to_string(),
+                name: name.to_string(),
+                output,
+            }],
+        );
+    }
+
+    /// Replace all but the newest `max` images in the history with a note, so a
+    /// long conversation doesn't resend every screenshot it has ever seen
+    pub fn keep_latest_images(&mut self, max: usize) {
+        let mut seen = 0;
+        for item in self.messages.iter_mut().rev().flat_map(|message| message.content.iter_mut().rev()) {
+            let is_image = match item {
+                Content::Image(_) => true,
+                Content::ToolResult { output, .. } => matches!(output, ToolOutput::Image(_)),
+                _ => false,
+            };
+            if !is_image {
+                continue;
+            }
+            seen += 1;
+            if seen <= max {
+                continue;
+            }
+            match item {
+                Content::ToolResult { output, .. } => *output = ToolOutput::Text(IMAGE_LEFT_OUT.to_string()),
+                _ => *item = Content::Text(IMAGE_LEFT_OUT.to_string()),
+            }
+        }
+    }
+
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    /// The history without thinking, for handing to another provider. Thinking
+    /// signatures only mean something to the provider that produced them.
+    pub fn without_thinking(&self) -> Self {
+        let messages = self
+            .messages
+            .iter()
+            .map(|message| Message {
+                role: message.role,
+                content: message
+                    .content
+                    .iter()
+                    .filter(|item| !matches!(item, Content::Thinking { .. }))
+                    .cloned()
+                    .collect(),
+            })
+            .filter(|message| !message.content.is_empty())
+            .collect();
+        Self { messages, pending: Vec::new() }
+    }
+
+    fn push_merged(messages: &mut Vec<Message>, role: Role, content: Vec<Content>) {
+        match messages.last_mut() {
+            Some(last) if last.role == role => last.content.extend(content),
+            _ => messages.push(Message { role, content }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn text(text: &str) -> Content {
+        Content::Text(text.to_string())
+    }
+
+    #[test]
+    fn pending_content_only_joins_the_history_once_committed() {
+        let mut conversation = Conversation::new();
+        conversation.add_content(text("First page"));
+        assert!(conversation.messages().is_empty());
+        assert_eq!(conversation.request_messages().len(), 1);
+
+        conversation.commit_user_turn();
+        conversation.add_assistant_turn(vec![text("First answer")]);
+
+        // A failed request is dropped, leaving the history as it was
+        conversation.add_content(text("Second page"));
+        conversation.clear_pending();
+        let messages = conversation.request_messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!((messages[0].role, messages[1].role), (Role::User, Role::Assistant));
+    }
+
+    #[test]
+    fn tool_results_and_the_next_turn_share_a_user_message() {
+        let mut conversation = Conversation::new();
+        conversation.add_content(text("Page"));
+        conversation.commit_user_turn();
+        conversation.add_assistant_turn(vec![Content::ToolCall {
+            id: "call_1".to_string(),
+            name: "lookup".to_string(),
+            arguments: json!({}),
+        }]);
+        conversation.add_tool_result("call_1", "lookup", ToolOutput::Text("result".to_string()));
+        conversation.add_content(text("Next page"));
+
+        let messages = conversation.request_messages();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[2].role, Role::User);
+        assert!(matches!(messages[2].content.as_slice(), [Content::ToolResult { .. }, Content::Text(next)] if next == "Next page"));
+
+        // Committing gives the same history the request was built from
+        conversation.commit_user_turn();
+        assert_eq!(conversation.messages().len(), 3);
+        assert_eq!(conversation.messages()[2].content.len(), 2);
+
+        conversation.clear();
+        assert!(conversation.request_messages().is_empty());
+    }
+
+    #[test]
+    fn keeps_only_the_latest_images() {
+        let mut conversation = Conversation::new();
+        for page in ["first", "second", "third"] {
+            conversation.add_content(Content::Image(page.to_string()));
+            conversation.add_content(text("Respond to the page"));
+            conversation.commit_user_turn();
+            conversation.add_assistant_turn(vec![Content::ToolCall {
+                id: page.to_string(),
+                name: "screenshot".to_string(),
+                arguments: json!({}),
+            }]);
+            conversation.add_tool_result(page, "screenshot", ToolOutput::Image(page.to_string()));
+        }
+        conversation.keep_latest_images(3);
+
+        let images = conversation
+            .messages()
+            .iter()
+            .flat_map(|message| message.content.iter())
+            .filter_map(|item| match item {
+                Content::Image(image)
+                | Content::ToolResult {
+                    output: ToolOutput::Image(image),
+                    ..
+                } => Some(image.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(images, vec!["second", "third", "third"]);
+        // The dropped ones leave a note behind, so every turn keeps its shape
+        assert!(matches!(&conversation.messages()[0].content[0], Content::Text(note) if note == IMAGE_LEFT_OUT));
+        assert_eq!(conversation.messages().len(), 7);
+    }
+
+    #[test]
+    fn handing_over_drops_thinking_and_pending_content() {
+        let mut conversation = Conversation::new();
+        conversation.add_content(text("What's on the page?"));
+        conversation.commit_user_turn();
+        conversation.add_assistant_turn(vec![
+            Content::Thinking {
+                thinking: "Hmm".to_string(),
+                signature: "sig".to_string(),
+            },
+            Content::ToolCall {
+                id: "call_1".to_string(),
+                name: "draw_text".to_string(),
+                arguments: json!({ "text": "hi" }),
+            },
+        ]);
+        conversation.add_tool_result("call_1", "draw_text", ToolOutput::Text("drawn".to_string()));
+        conversation.add_content(text("Not sent yet"));
+
+        let handed_over = conversation.without_thinking();
+        assert_eq!(handed_over.messages().len(), 3);
+        assert!(matches!(handed_over.messages()[1].content.as_slice(), [Content::ToolCall { id, .. }] if id == "call_1"));
+        assert_eq!(handed_over.request_messages().len(), 3);
+    }
+}
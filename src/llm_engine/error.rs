@@ -0,0 +1,194 @@
+use serde_json::Value as json;
+use std::fmt;
+use std::time::Duration;
+
+/// Why a request to a model failed. The category decides whether retrying
+/// makes sense and is what gets shown on the device.
+#[derive(Debug)]
+pub enum EngineError {
+    /// Bad or missing API key, or no access to the model
+    Auth(String),
+    RateLimited {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// The provider is temporarily out of capacity
+    Overloaded {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    Timeout(String),
+    /// Couldn't connect, or the connection dropped
+    Network(String),
+    /// The model was sent tools but can't use them (Ollama answers 400 for these)
+    ToolsUnsupported(String),
+    /// Any other non-success HTTP status
+    Http {
+        status: u16,
+        message: String,
+    },
+    /// An error reported inside an otherwise successful response (like mid-stream)
+    Api(String),
+    /// The response didn't have the shape we expected
+    MalformedResponse(String),
+    /// A replay cassette couldn't be read or has no recording of the request
+    Cassette(String),
+    /// The model answered without calling any tool
+    NoToolCall,
+    Cancelled,
+}
+
+impl EngineError {
+    /// Classify a non-success HTTP response
+    pub fn from_status(status: u16, body: &str, retry_after: Option<Duration>) -> Self {
+        let message = error_message(body);
+        match status {
+            401 | 403 => EngineError::Auth(message),
+            429 => EngineError::RateLimited { message, retry_after },
+            503 | 529 => EngineError::Overloaded { message, retry_after },
+            408 | 504 => EngineError::Timeout(message),
+            400 if message.contains("does not support tools") => EngineError::ToolsUnsupported(message),
+            _ => EngineError::Http { status, message },
+        }
+    }
+
+    /// Classify an error object that arrived in a response body or stream event
+    pub fn from_api_error(error: &json) -> Self {
+        let kind = [&error["type"], &error["code"], &error["status"], &error["error"]["type"]]
+            .iter()
+            .filter_map(|value| value.as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase();
+        let message = error["message"]
+            .as_str()
+            .or(error["error"]["message"].as_str())
+            .map_or_else(|| error.to_string(), |m| m.to_string());
+
+        if kind.contains("overloaded") || kind.contains("unavailable") {
+            EngineError::Overloaded { message, retry_after: None }
+        } else if kind.contains("rate_limit") || kind.contains("resource_exhausted") {
+            EngineError::RateLimited { message, retry_after: None }
+        } else if kind.contains("authentication") || kind.contains("permission") || kind.contains("unauthenticated") || kind.contains("invalid_api_key") {
+            EngineError::Auth(message)
+        } else {
+            EngineError::Api(message)
+        }
+    }
+
+    /// Worth trying the same request again after a pause
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            EngineError::RateLimited { .. } | EngineError::Overloaded { .. } | EngineError::Timeout(_) | EngineError::Network(_) => true,
+            EngineError::Http { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// How long the provider asked us to wait, if it said
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            EngineError::RateLimited { retry_after, .. } | EngineError::Overloaded { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Short label, short enough to type onto the page
+    pub fn category(&self) -> &'static str {
+        match self {
+            EngineError::Auth(_) => "auth error",
+            EngineError::RateLimited { .. } => "rate limited",
+            EngineError::Overloaded { .. } => "overloaded",
+            EngineError::Timeout(_) => "timeout",
+            EngineError::Network(_) => "network error",
+            EngineError::ToolsUnsupported(_) => "no tool support",
+            EngineError::Http { .. } => "http error",
+            EngineError::Api(_) => "api error",
+            EngineError::MalformedResponse(_) => "bad response",
+            EngineError::Cassette(_) => "cassette error",
+            EngineError::NoToolCall => "no tool call",
+            EngineError::Cancelled => "cancelled",
+        }
+    }
+
+    /// The category of any error coming out of an engine, for errors that aren't an `EngineError`
+    /// (like a tool failing in an unexpected way) this is just "model error"
+    pub fn category_of(error: &anyhow::Error) -> &'static str {
+        error.downcast_ref::<EngineError>().map_or("model error", |e| e.category())
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Auth(message) => write!(f, "Authentication failed: {}", message),
+            EngineError::RateLimited { message, .. } => write!(f, "Rate limited: {}", message),
+            EngineError::Overloaded { message, .. } => write!(f, "Provider overloaded: {}", message),
+            EngineError::Timeout(message) => write!(f, "Request timed out: {}", message),
+            EngineError::Network(message) => write!(f, "Network error: {}", message),
+            EngineError::ToolsUnsupported(message) => write!(f, "Model does not support tools: {}", message),
+            EngineError::Http { status: 0, message } => write!(f, "Request failed: {}", message),
+            EngineError::Http { status, message } => write!(f, "HTTP {}: {}", status, message),
+            EngineError::Api(message) => write!(f, "API error: {}", message),
+            EngineError::MalformedResponse(message) => write!(f, "Malformed response: {}", message),
+            EngineError::Cassette(message) => write!(f, "Cassette: {}", message),
+            EngineError::NoToolCall => write!(f, "No tool calls found in response"),
+            EngineError::Cancelled => write!(f, "Request cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+/// Pull the human readable message out of an error response body, falling back to the raw body
+fn error_message(body: &str) -> String {
+    if let Ok(parsed) = serde_json::from_str::<json>(body) {
+        let error = if parsed["error"].is_object() { &parsed["error"] } else { &parsed };
+        if let Some(message) = error["message"].as_str() {
+            return message.to_string();
+        }
+        if let Some(message) = parsed["error"].as_str() {
+            return message.to_string();
+        }
+    }
+    body.chars().take(300).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn classifies_http_statuses() {
+        let body = r#"{"error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+        assert!(matches!(EngineError::from_status(529, body, None), EngineError::Overloaded { message, .. } if message == "Overloaded"));
+        assert!(matches!(EngineError::from_status(401, "nope", None), EngineError::Auth(message) if message == "nope"));
+        assert!(matches!(
+            EngineError::from_status(429, "{}", Some(Duration::from_secs(2))).retry_after(),
+            Some(delay) if delay == Duration::from_secs(2)
+        ));
+        assert!(EngineError::from_status(500, "{}", None).is_retryable());
+        assert!(!EngineError::from_status(400, "{}", None).is_retryable());
+    }
+
+    #[test]
+    fn classifies_errors_inside_responses() {
+        assert_eq!(
+            EngineError::from_api_error(&json!({ "type": "rate_limit_error", "message": "slow" })).category(),
+            "rate limited"
+        );
+        assert_eq!(
+            EngineError::from_api_error(&json!({ "code": 503, "status": "UNAVAILABLE" })).category(),
+            "overloaded"
+        );
+        assert_eq!(EngineError::from_api_error(&json!({ "code": "invalid_api_key" })).category(), "auth error");
+        assert_eq!(EngineError::from_api_error(&json!({ "message": "odd" })).category(), "api error");
+    }
+
+    #[test]
+    fn category_of_looks_through_anyhow() {
+        assert_eq!(EngineError::category_of(&EngineError::NoToolCall.into()), "no tool call");
+        assert_eq!(EngineError::category_of(&anyhow::anyhow!("tool exploded")), "model error");
+    }
+}
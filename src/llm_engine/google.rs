@@ -1,40 +1,25 @@
-use super::LLMEngine;
+use super::conversation::{Content, Message, Role};
+use super::error::EngineError;
+use super::http::read_json;
+use super::sse::read_events;
+use super::{record_exchange, EngineBackend, EngineState, Tool, ToolCall, ToolOutput, Usage};
 use crate::util::{option_or_env, option_or_env_fallback, OptionMap};
 use anyhow::Result;
-use log::{debug, info};
+use log::debug;
 use serde_json::json;
 use serde_json::Value as json;
-
-pub struct Tool {
-    name: String,
-    definition: json,
-    callback: Option<Box<dyn FnMut(json)>>,
-}
+use std::io::Read;
+use std::time::Instant;
 
 pub struct Google {
     model: String,
     base_url: String,
     api_key: String,
-    tools: Vec<Tool>,
-    content: Vec<json>,
+    state: EngineState,
 }
 
 impl Google {
-    fn google_tool_definition(tool: &Tool) -> json {
-        json!({
-            "name": tool.definition["name"],
-            "description": tool.definition["description"],
-            "parameters": tool.definition["parameters"],
-        })
-    }
-
-    pub fn add_content(&mut self, content: json) {
-        self.content.push(content);
-    }
-}
-
-impl LLMEngine for Google {
-    fn new(options: &OptionMap) -> Self {
+    pub fn new(options: &OptionMap) -> Self {
         let api_key = option_or_env(options, "api_key", "GOOGLE_API_KEY");
         let base_url = option_or_env_fallback(options, "base_url", "GOOGLE_BASE_URL", "https://generativelanguage.googleapis.com");
         let model = options.get("model").unwrap().to_string();
@@ -43,45 +28,123 @@ impl LLMEngine for Google {
             model,
             base_url,
             api_key,
-            tools: Vec::new(),
-            content: Vec::new(),
+            state: EngineState::new(options),
         }
     }
 
-    fn register_tool(&mut self, name: &str, definition: json, callback: Box<dyn FnMut(json)>) {
-        self.tools.push(Tool {
-            name: name.to_string(),
-            definition,
-            callback: Some(callback),
-        });
+    fn google_tool_definition(tool: &Tool) -> json {
+        json!({
+            "name": tool.definition["name"],
+            "description": tool.definition["description"],
+            "parameters": tool.definition["parameters"],
+        })
+    }
+
+    fn google_parts(content: &Content) -> Vec<json> {
+        match content {
+            Content::Text(text) => vec![json!({
+                "text": text,
+            })],
+            Content::Image(base64_image) => vec![json!({
+                "inline_data": {
+                    "mime_type": "image/png",
+                    "data": base64_image,
+                }
+            })],
+            Content::ToolCall { name, arguments, .. } => vec![json!({
+                "functionCall": {
+                    "name": name,
+                    "args": arguments,
+                }
+            })],
+            Content::ToolResult { name, output, .. } => match output {
+                ToolOutput::Text(text) => vec![json!({
+                    "functionResponse": {
+                        "name": name,
+                        "response": { "content": text },
+                    }
+                })],
+                ToolOutput::Error(error) => vec![json!({
+                    "functionResponse": {
+                        "name": name,
+                        "response": { "error": error },
+                    }
+                })],
+                ToolOutput::Image(base64_image) => {
+                    let mut parts = vec![json!({
+                        "functionResponse": {
+                            "name": name,
+                            "response": { "content": "The resulting image is attached." },
+                        }
+                    })];
+                    parts.extend(Self::google_parts(&Content::Image(base64_image.clone())));
+                    parts
+                }
+            },
+            Content::Thinking { .. } => vec![],
+        }
     }
 
-    fn add_text_content(&mut self, text: &str) {
-        self.add_content(json!({
-            "text": text,
-        }));
+    fn google_message(message: &Message) -> json {
+        json!({
+            "role": match message.role {
+                Role::User => "user",
+                Role::Assistant => "model",
+            },
+            "parts": message.content.iter().flat_map(Self::google_parts).collect::<Vec<_>>(),
+        })
     }
 
-    fn add_image_content(&mut self, base64_image: &str) {
-        self.add_content(json!({
-            "inline_data": {
-                "mime_type": "image/png",
-                "data": base64_image,
+    /// Reassemble a streamed response into the same shape as a regular one
+    fn read_stream(&mut self, reader: impl Read) -> Result<json> {
+        let mut parts: Vec<json> = Vec::new();
+        let mut last_chunk = json!({});
+        let progress = &mut self.state.progress;
+
+        read_events(reader, &self.state.cancel, |_event, data| {
+            if !data["error"].is_null() {
+                return Err(EngineError::from_api_error(&data["error"]).into());
             }
-        }));
+
+            for part in data["candidates"][0]["content"]["parts"].as_array().unwrap_or(&Vec::new()) {
+                match (part["text"].as_str(), parts.last_mut()) {
+                    // Text arrives in pieces, so glue it onto the previous text part
+                    (Some(text), Some(previous)) if previous["text"].is_string() => {
+                        let joined = format!("{}{}", previous["text"].as_str().unwrap_or(""), text);
+                        previous["text"] = json!(joined);
+                    }
+                    _ => parts.push(part.clone()),
+                }
+                if let Some(progress) = progress {
+                    match part["text"].as_str() {
+                        Some(text) => progress(text),
+                        None => progress(&part["functionCall"].to_string()),
+                    }
+                }
+            }
+            last_chunk = data.clone();
+            Ok(())
+        })?;
+
+        // Keep the metadata (like usage) from the final chunk
+        last_chunk["candidates"] = json!([{ "content": { "role": "model", "parts": parts } }]);
+        Ok(last_chunk)
+    }
+}
+
+impl EngineBackend for Google {
+    fn state(&self) -> &EngineState {
+        &self.state
     }
 
-    fn clear_content(&mut self) {
-        self.content.clear();
+    fn state_mut(&mut self) -> &mut EngineState {
+        &mut self.state
     }
 
-    fn execute(&mut self) -> Result<()> {
+    fn send_request(&mut self) -> Result<json> {
         let body = json!({
-            "contents": [{
-                "role": "user",
-                "parts": self.content
-            }],
-            "tools": [{ "function_declarations": self.tools.iter().map(Self::google_tool_definition).collect::<Vec<_>>() }],
+            "contents": self.state.conversation.request_messages().iter().map(Self::google_message).collect::<Vec<_>>(),
+            "tools": [{ "function_declarations": self.state.tools.iter().map(Self::google_tool_definition).collect::<Vec<_>>() }],
             "tool_config": {
                 "function_calling_config": {
                     "mode": "ANY"
@@ -91,42 +154,104 @@ impl LLMEngine for Google {
 
         // print body for debugging
         debug!("Request: {}", body);
-        let raw_response = ureq::post(format!("{}/v1beta/models/{}:generateContent?key={}", self.base_url, self.model, self.api_key).as_str())
-            .header("Content-Type", "application/json")
-            .send_json(&body);
-
-        let mut response = match raw_response {
-            Ok(response) => response,
-            Err(err) => {
-                info!("API Error: {}", err);
-                return Err(anyhow::anyhow!("API ERROR: {}", err));
-            }
+        let url = if self.state.stream {
+            format!(
+                "{}/v1beta/models/{}:streamGenerateContent?alt=sse&key={}",
+                self.base_url, self.model, self.api_key
+            )
+        } else {
+            format!("{}/v1beta/models/{}:generateContent?key={}", self.base_url, self.model, self.api_key)
         };
+        let started = Instant::now();
+        let mut response = self.state.post(&url, &[("Content-Type", "application/json")], &body)?;
 
-        // Read response body as string
-        let body_text = response.body_mut().read_to_string().unwrap();
-        let json: json = serde_json::from_str(&body_text).unwrap();
+        let json = if self.state.stream {
+            self.read_stream(response.body_mut().as_reader())?
+        } else {
+            read_json(&mut response)?
+        };
         debug!("Response: {}", json);
+        record_exchange(&mut self.state.exchange_callback, &body, &json, started);
+        Ok(json)
+    }
 
-        let tool_calls = &json["candidates"][0]["content"]["parts"];
-
-        if let Some(tool_call) = tool_calls.get(0) {
-            let function_name = tool_call["functionCall"]["name"].as_str().unwrap();
-            let function_input = &tool_call["functionCall"]["args"];
-            let tool = self.tools.iter_mut().find(|tool| tool.name == function_name);
+    fn parse_response(&self, response: &json, _step: u32) -> Result<(Vec<Content>, Vec<ToolCall>)> {
+        let mut assistant_content = Vec::new();
+        let mut tool_calls = Vec::new();
 
-            if let Some(tool) = tool {
-                if let Some(callback) = &mut tool.callback {
-                    callback(function_input.clone());
-                    Ok(())
-                } else {
-                    Err(anyhow::anyhow!("No callback registered for tool {}", function_name))
-                }
-            } else {
-                Err(anyhow::anyhow!("No tool registered with name {}", function_name))
+        // Gemini matches function responses by name, but other engines given this history
+        // need an id per call. The history length keeps them apart from earlier turns' calls.
+        let turn = self.state.conversation.messages().len();
+        for (index, part) in response["candidates"][0]["content"]["parts"]
+            .as_array()
+            .unwrap_or(&Vec::new())
+            .iter()
+            .enumerate()
+        {
+            if let Some(function_call) = part.get("functionCall") {
+                let function_name = function_call["name"].as_str().unwrap_or_default().to_string();
+                let function_input = function_call["args"].clone();
+                let id = format!("{}_{}_{}", function_name, turn, index);
+                assistant_content.push(Content::ToolCall {
+                    id: id.clone(),
+                    name: function_name.clone(),
+                    arguments: function_input.clone(),
+                });
+                tool_calls.push(ToolCall {
+                    id,
+                    name: function_name,
+                    arguments: function_input,
+                });
+            } else if let Some(text) = part["text"].as_str() {
+                debug!("Text: {}", text);
+                assistant_content.push(Content::Text(text.to_string()));
             }
-        } else {
-            Err(anyhow::anyhow!("No tool calls found in response"))
         }
+
+        Ok((assistant_content, tool_calls))
+    }
+
+    fn parse_usage(&self, response: &json) -> Usage {
+        let usage = &response["usageMetadata"];
+        let thinking_tokens = usage["thoughtsTokenCount"].as_u64().unwrap_or(0);
+        Usage {
+            input_tokens: usage["promptTokenCount"].as_u64().unwrap_or(0),
+            output_tokens: usage["candidatesTokenCount"].as_u64().unwrap_or(0) + thinking_tokens,
+            thinking_tokens,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gives_each_call_its_own_id_but_answers_by_name() {
+        let mut options = OptionMap::new();
+        options.insert("model".to_string(), "gemini-2.5-flash".to_string());
+        options.insert("api_key".to_string(), "test".to_string());
+        let mut engine = Google::new(&options);
+        let response = json!({ "candidates": [{ "content": { "parts": [
+            { "functionCall": { "name": "draw_text", "args": { "text": "a" } } },
+            { "functionCall": { "name": "draw_text", "args": { "text": "b" } } },
+        ] } }] });
+
+        let (_, first) = engine.parse_response(&response, 1).unwrap();
+        engine.state.conversation.add_content(Content::Text("Page".to_string()));
+        engine.state.conversation.commit_user_turn();
+        let (_, second) = engine.parse_response(&response, 1).unwrap();
+        let ids = first.iter().chain(&second).map(|call| call.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, ["draw_text_0_0", "draw_text_0_1", "draw_text_1_0", "draw_text_1_1"]);
+
+        let result = Content::ToolResult {
+            id: ids[1].to_string(),
+            name: "draw_text".to_string(),
+            output: ToolOutput::Text("drawn".to_string()),
+        };
+        assert_eq!(
+            Google::google_parts(&result),
+            [json!({ "functionResponse": { "name": "draw_text", "response": { "content": "drawn" } } })]
+        );
     }
 }
@@ -0,0 +1,185 @@
+use super::cassette::Cassette;
+use super::error::EngineError;
+use log::{debug, warn};
+use serde_json::Value as json;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::sleep;
+use std::time::Duration;
+use ureq::http::Response;
+use ureq::Body;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+// Without streaming the headers only arrive once the whole answer is generated
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(300);
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// If the provider wants us to wait longer than this, give up instead of leaving the page hanging
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(60);
+
+/// POST a JSON body, retrying rate limits, overloads, timeouts, and server errors
+/// up to `max_retries` times with exponential backoff (or the server's `retry-after`).
+/// With a cassette the exchange is recorded, or replayed without touching the network.
+pub fn post_json(
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &json,
+    max_retries: u32,
+    cancel: &AtomicBool,
+    cassette: Option<&Cassette>,
+) -> Result<Response<Body>, EngineError> {
+    // A replayed error would only come back the same way
+    let max_retries = if cassette.is_some_and(Cassette::is_replay) { 0 } else { max_retries };
+    let mut attempt = 0;
+    loop {
+        if cancel.load(Ordering::SeqCst) {
+            return Err(EngineError::Cancelled);
+        }
+
+        let error = match send(url, headers, body, cassette) {
+            Ok(response) => return Ok(response),
+            Err(error) => error,
+        };
+
+        if !error.is_retryable() || attempt >= max_retries {
+            return Err(error);
+        }
+        let delay = error.retry_after().unwrap_or_else(|| (INITIAL_BACKOFF * 2u32.pow(attempt)).min(MAX_BACKOFF));
+        if delay > MAX_RETRY_WAIT {
+            warn!("{}; asked to wait {}s, not retrying", error, delay.as_secs());
+            return Err(error);
+        }
+
+        attempt += 1;
+        warn!("{}; retrying in {:.1}s (retry {} of {})", error, delay.as_secs_f32(), attempt, max_retries);
+        wait(delay, cancel)?;
+    }
+}
+
+/// Read a whole response body as JSON
+pub fn read_json(response: &mut Response<Body>) -> Result<json, EngineError> {
+    let body_text = response.body_mut().read_to_string().map_err(classify_transport_error)?;
+    serde_json::from_str(&body_text).map_err(|e| EngineError::MalformedResponse(format!("{} in {}", e, body_text.chars().take(300).collect::<String>())))
+}
+
+fn send(url: &str, headers: &[(&str, &str)], body: &json, cassette: Option<&Cassette>) -> Result<Response<Body>, EngineError> {
+    if let Some(cassette) = cassette.filter(|cassette| cassette.is_replay()) {
+        let (status, response) = cassette.lookup(url, body)?;
+        let response = Response::builder()
+            .status(status)
+            .body(Body::builder().data(response))
+            .map_err(|e| EngineError::MalformedResponse(e.to_string()))?;
+        return check_status(response);
+    }
+
+    let mut request = ureq::post(url)
+        .config()
+        .http_status_as_error(false)
+        .timeout_connect(Some(CONNECT_TIMEOUT))
+        .timeout_recv_response(Some(RESPONSE_TIMEOUT))
+        .build();
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+
+    let mut response = request.send_json(body).map_err(classify_transport_error)?;
+
+    if let Some(cassette) = cassette {
+        // Streams are read whole so they can be written down, then handed on as if fresh
+        let text = response.body_mut().read_to_string().map_err(classify_transport_error)?;
+        cassette.record(url, body, response.status().as_u16(), &text);
+        let (parts, _) = response.into_parts();
+        response = Response::from_parts(parts, Body::builder().data(text));
+    }
+
+    check_status(response)
+}
+
+/// Pass a successful response through, turn anything else into an error
+fn check_status(mut response: Response<Body>) -> Result<Response<Body>, EngineError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let retry_after = retry_after(&response);
+    let body_text = response.body_mut().read_to_string().unwrap_or_default();
+    debug!("API error response {}: {}", status, body_text);
+    Err(EngineError::from_status(status.as_u16(), &body_text, retry_after))
+}
+
+/// `retry-after` in seconds, or OpenAI's `retry-after-ms`
+fn retry_after(response: &Response<Body>) -> Option<Duration> {
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<f64>().ok())
+    };
+    header("retry-after-ms")
+        .map(|ms| Duration::from_secs_f64(ms.max(0.0) / 1000.0))
+        .or_else(|| header("retry-after").map(|seconds| Duration::from_secs_f64(seconds.max(0.0))))
+}
+
+pub fn classify_transport_error(error: ureq::Error) -> EngineError {
+    match error {
+        ureq::Error::Timeout(timeout) => EngineError::Timeout(format!("{:?}", timeout)),
+        ureq::Error::Io(e) if e.kind() == std::io::ErrorKind::TimedOut => EngineError::Timeout(e.to_string()),
+        ureq::Error::Io(e) => EngineError::Network(e.to_string()),
+        ureq::Error::HostNotFound | ureq::Error::ConnectionFailed => EngineError::Network(error.to_string()),
+        error => EngineError::Http {
+            status: 0,
+            message: error.to_string(),
+        },
+    }
+}
+
+/// Sleep in small steps so a cancel doesn't have to wait out the whole backoff
+fn wait(delay: Duration, cancel: &AtomicBool) -> Result<(), EngineError> {
+    let step = Duration::from_millis(100);
+    let mut waited = Duration::ZERO;
+    while waited < delay {
+        if cancel.load(Ordering::SeqCst) {
+            return Err(EngineError::Cancelled);
+        }
+        sleep(step.min(delay - waited));
+        waited += step;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(headers: &[(&str, &str)]) -> Response<Body> {
+        let mut builder = Response::builder().status(429);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::builder().data("")).unwrap()
+    }
+
+    #[test]
+    fn reads_retry_after_seconds() {
+        assert_eq!(retry_after(&response(&[("retry-after", "2")])), Some(Duration::from_secs(2)));
+        assert_eq!(retry_after(&response(&[("retry-after", " 1.5 ")])), Some(Duration::from_millis(1500)));
+        assert_eq!(retry_after(&response(&[("retry-after", "-3")])), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn prefers_retry_after_ms() {
+        assert_eq!(
+            retry_after(&response(&[("retry-after", "20"), ("retry-after-ms", "250")])),
+            Some(Duration::from_millis(250))
+        );
+    }
+
+    #[test]
+    fn ignores_missing_or_unparsable_retry_after() {
+        assert_eq!(retry_after(&response(&[])), None);
+        // An HTTP date isn't worth parsing, the backoff takes over
+        assert_eq!(retry_after(&response(&[("retry-after", "Wed, 21 Oct 2026 07:28:00 GMT")])), None);
+    }
+}
@@ -1,18 +1,353 @@
 pub mod anthropic;
+pub mod cassette;
+pub mod conversation;
+pub mod error;
 pub mod google;
+pub mod http;
+pub mod ollama;
 pub mod openai;
+pub mod openai_responses;
+pub mod sse;
 
 use anyhow::Result;
+use log::{debug, info, warn};
+use serde::Serialize;
 use serde_json::Value as json;
-use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use ureq::http::Response;
+use ureq::Body;
+
+use crate::util::OptionMap;
+use cassette::Cassette;
+use conversation::{Content, Conversation};
+use error::EngineError;
+
+/// What a tool callback hands back to the model
+#[derive(Debug, Clone)]
+pub enum ToolOutput {
+    Text(String),
+    Image(String), // base64 encoded PNG
+    Error(String),
+}
+
+impl ToolOutput {
+    pub fn is_error(&self) -> bool {
+        matches!(self, ToolOutput::Error(_))
+    }
+}
+
+/// A tool invocation requested by the model
+#[derive(Debug, Clone)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: json,
+}
+
+/// Tokens used by one `execute()`, summed over all of its steps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Usage {
+    pub input_tokens: u64,
+    /// Everything the model generated, including thinking
+    pub output_tokens: u64,
+    /// The part of the output spent thinking, when the provider reports it separately
+    pub thinking_tokens: u64,
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.thinking_tokens += other.thinking_tokens;
+    }
+}
+
+/// One request to the provider and the (reassembled, if streamed) response
+#[derive(Debug, Clone, Serialize)]
+pub struct Exchange {
+    pub request: json,
+    pub response: json,
+    pub duration_ms: u128,
+}
+
+/// Receives every exchange with the provider, for recording
+pub type ExchangeCallback = Box<dyn FnMut(Exchange)>;
+
+pub fn record_exchange(callback: &mut Option<ExchangeCallback>, request: &json, response: &json, started: Instant) {
+    if let Some(callback) = callback {
+        callback(Exchange {
+            request: request.clone(),
+            response: response.clone(),
+            duration_ms: started.elapsed().as_millis(),
+        });
+    }
+}
+
+pub type ToolCallback = Box<dyn FnMut(json) -> ToolOutput>;
+
+/// Receives partial text (thinking, text, or tool arguments) while a response streams in
+pub type ProgressCallback = Box<dyn FnMut(&str)>;
+
+pub struct Tool {
+    pub name: String,
+    pub definition: json,
+    pub callback: Option<ToolCallback>,
+}
+
+impl Tool {
+    /// Tools declared with `"next_action": "loop"` have their result sent back to
+    /// the model for another step. Everything else (like drawing) ends the turn.
+    pub fn loops(&self) -> bool {
+        self.definition["next_action"].as_str() == Some("loop")
+    }
+}
+
+/// Invoke the named tool. Returns the output for the model and whether the model
+/// should get another step to react to it.
+pub fn run_tool(tools: &mut [Tool], name: &str, arguments: json) -> (ToolOutput, bool) {
+    let Some(tool) = tools.iter_mut().find(|tool| tool.name == name) else {
+        warn!("Model called unknown tool {}", name);
+        return (ToolOutput::Error(format!("No tool registered with name {}", name)), true);
+    };
+
+    let loops = tool.loops();
+    let output = match &mut tool.callback {
+        Some(callback) => callback(arguments),
+        None => ToolOutput::Error(format!("No callback registered for tool {}", name)),
+    };
+    debug!("Tool {} output: {:?}", name, output);
+
+    // Give the model a chance to recover from errors
+    let continue_loop = loops || output.is_error();
+    (output, continue_loop)
+}
 
 pub trait LLMEngine {
-    fn new(options: &HashMap<String, String>) -> Self
-    where
-        Self: Sized;
-    fn register_tool(&mut self, name: &str, definition: json, callback: Box<dyn FnMut(json)>);
+    fn register_tool(&mut self, name: &str, definition: json, callback: ToolCallback);
     fn add_text_content(&mut self, text: &str);
     fn add_image_content(&mut self, base64_image: &str);
     fn clear_content(&mut self);
-    fn execute(&mut self) -> Result<()>;
+    fn clear_history(&mut self);
+    fn history(&self) -> &Conversation;
+    /// Continue from a history another engine built, like after it answered as a fallback
+    fn set_history(&mut self, conversation: &Conversation);
+    fn set_progress_callback(&mut self, callback: ProgressCallback);
+    /// Once the flag is set, a streaming request in flight stops with an error
+    fn set_cancel_flag(&mut self, cancel: Arc<AtomicBool>);
+    fn set_exchange_callback(&mut self, callback: ExchangeCallback);
+    /// Run the model (and any tools it calls) on the pending content, returning the tokens used
+    fn execute(&mut self) -> Result<Usage>;
+    /// Tokens used by the latest `execute()`, including one that failed after some requests went through
+    fn last_usage(&self) -> Usage;
+}
+
+/// What every engine keeps whatever the provider: the tools, the conversation,
+/// and the settings for sending requests
+pub struct EngineState {
+    pub tools: Vec<Tool>,
+    pub conversation: Conversation,
+    pub max_steps: u32,
+    pub max_retries: u32,
+    /// Images the history keeps, newest first; older ones are replaced by a note
+    pub max_history_images: usize,
+    pub cassette: Option<Cassette>,
+    pub stream: bool,
+    pub progress: Option<ProgressCallback>,
+    pub exchange_callback: Option<ExchangeCallback>,
+    pub cancel: Arc<AtomicBool>,
+    /// Tokens used by the latest `execute()` so far, kept when it fails partway
+    pub usage: Usage,
+}
+
+impl EngineState {
+    /// From the "max_steps", "max_retries", "max_history_images", "stream" and cassette engine options
+    pub fn new(options: &OptionMap) -> Self {
+        Self {
+            tools: Vec::new(),
+            conversation: Conversation::new(),
+            max_steps: options.get("max_steps").and_then(|v| v.parse::<u32>().ok()).unwrap_or(5),
+            max_retries: options.get("max_retries").and_then(|v| v.parse::<u32>().ok()).unwrap_or(3),
+            max_history_images: options.get("max_history_images").and_then(|v| v.parse::<usize>().ok()).unwrap_or(4),
+            cassette: Cassette::from_options(options),
+            stream: options.get("stream").is_some_and(|v| v == "true"),
+            progress: None,
+            exchange_callback: None,
+            cancel: Arc::new(AtomicBool::new(false)),
+            usage: Usage::default(),
+        }
+    }
+
+    /// POST a request body with this engine's retries, cancel flag and cassette
+    pub fn post(&self, url: &str, headers: &[(&str, &str)], body: &json) -> Result<Response<Body>, EngineError> {
+        http::post_json(url, headers, body, self.max_retries, &self.cancel, self.cassette.as_ref())
+    }
+
+    pub fn check_cancelled(&self) -> Result<(), EngineError> {
+        if self.cancel.load(Ordering::SeqCst) {
+            return Err(EngineError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// The provider-specific half of an engine: building requests and reading
+/// responses. The agentic loop, tools and history handling on top of it are
+/// shared, through the `LLMEngine` implementation below.
+pub trait EngineBackend {
+    fn state(&self) -> &EngineState;
+    fn state_mut(&mut self) -> &mut EngineState;
+    /// Send the history plus the pending user turn, returning the response (reassembled, if streamed)
+    fn send_request(&mut self) -> Result<json>;
+    /// Split a response into the assistant turn (for history) and the tool calls to run
+    fn parse_response(&self, response: &json, step: u32) -> Result<(Vec<Content>, Vec<ToolCall>)>;
+    fn parse_usage(&self, response: &json) -> Usage;
+    /// Called once a response is part of the history
+    fn response_committed(&mut self, _response: &json) {}
+    /// Called after the history is cleared, to forget anything kept alongside it
+    fn history_cleared(&mut self) {}
+}
+
+impl<T: EngineBackend> LLMEngine for T {
+    fn register_tool(&mut self, name: &str, definition: json, callback: ToolCallback) {
+        self.state_mut().tools.push(Tool {
+            name: name.to_string(),
+            definition,
+            callback: Some(callback),
+        });
+    }
+
+    fn add_text_content(&mut self, text: &str) {
+        self.state_mut().conversation.add_content(Content::Text(text.to_string()));
+    }
+
+    fn add_image_content(&mut self, base64_image: &str) {
+        self.state_mut().conversation.add_content(Content::Image(base64_image.to_string()));
+    }
+
+    fn clear_content(&mut self) {
+        self.state_mut().conversation.clear_pending();
+    }
+
+    fn clear_history(&mut self) {
+        self.state_mut().conversation.clear();
+        self.history_cleared();
+    }
+
+    fn history(&self) -> &Conversation {
+        &self.state().conversation
+    }
+
+    fn set_history(&mut self, conversation: &Conversation) {
+        self.state_mut().conversation = conversation.without_thinking();
+        self.history_cleared();
+    }
+
+    fn set_progress_callback(&mut self, callback: ProgressCallback) {
+        self.state_mut().progress = Some(callback);
+    }
+
+    fn set_cancel_flag(&mut self, cancel: Arc<AtomicBool>) {
+        self.state_mut().cancel = cancel;
+    }
+
+    fn set_exchange_callback(&mut self, callback: ExchangeCallback) {
+        self.state_mut().exchange_callback = Some(callback);
+    }
+
+    fn execute(&mut self) -> Result<Usage> {
+        run_agent_loop(self)
+    }
+
+    fn last_usage(&self) -> Usage {
+        self.state().usage
+    }
+}
+
+/// Send the pending content, run the tools the model calls, and keep going while
+/// a tool wants the model to see its result, for at most `max_steps` requests
+pub fn run_agent_loop(engine: &mut impl EngineBackend) -> Result<Usage> {
+    let max_steps = engine.state().max_steps;
+    let mut tool_called = false;
+    engine.state_mut().usage = Usage::default();
+
+    for step in 1..=max_steps {
+        engine.state().check_cancelled()?;
+        debug!("Step {} of at most {}", step, max_steps);
+        let response = engine.send_request()?;
+        let usage = engine.parse_usage(&response);
+        engine.state_mut().usage += usage;
+        // A response that arrives after a cancel is paid for, but dropped rather than acted on
+        engine.state().check_cancelled()?;
+        let (assistant_content, tool_calls) = engine.parse_response(&response, step)?;
+
+        let state = engine.state_mut();
+        state.conversation.commit_user_turn();
+        state.conversation.keep_latest_images(state.max_history_images);
+        state.conversation.add_assistant_turn(assistant_content);
+        engine.response_committed(&response);
+
+        if tool_calls.is_empty() {
+            if tool_called {
+                return Ok(engine.state().usage);
+            }
+            return Err(EngineError::NoToolCall.into());
+        }
+        tool_called = true;
+
+        let mut continue_loop = false;
+        let state = engine.state_mut();
+        for tool_call in tool_calls {
+            // Once cancelled, leave the page alone but still answer every call so the history stays valid
+            let (output, loops) = match state.check_cancelled() {
+                Ok(()) => run_tool(&mut state.tools, &tool_call.name, tool_call.arguments),
+                Err(e) => (ToolOutput::Error(e.to_string()), false),
+            };
+            continue_loop |= loops;
+            state.conversation.add_tool_result(&tool_call.id, &tool_call.name, output);
+        }
+        state.check_cancelled()?;
+
+        if !continue_loop {
+            return Ok(engine.state().usage);
+        }
+    }
+
+    info!("Stopping after {} tool steps", max_steps);
+    Ok(engine.state().usage)
+}
+
+/// Guess the engine from the model name when it isn't given
+pub fn determine_engine_name(engine_arg: &Option<String>, model: &str) -> Result<String> {
+    if let Some(engine) = engine_arg {
+        return Ok(engine.clone());
+    }
+
+    if model.starts_with("gpt") {
+        Ok("openai".to_string())
+    } else if model.starts_with("claude") {
+        Ok("anthropic".to_string())
+    } else if model.starts_with("gemini") {
+        Ok("google".to_string())
+    } else {
+        Err(anyhow::anyhow!(
+            "Unable to guess engine from model name '{}'. Please specify --engine (openai, openai-responses, anthropic, google, or ollama)",
+            model
+        ))
+    }
+}
+
+pub fn create_engine(engine_name: &str, engine_options: &OptionMap) -> Result<Box<dyn LLMEngine>> {
+    match engine_name {
+        "openai" => Ok(Box::new(openai::OpenAI::new(engine_options))),
+        "anthropic" => Ok(Box::new(anthropic::Anthropic::new(engine_options))),
+        "google" => Ok(Box::new(google::Google::new(engine_options))),
+        "openai-responses" => Ok(Box::new(openai_responses::OpenAIResponses::new(engine_options))),
+        "ollama" => Ok(Box::new(ollama::Ollama::new(engine_options))),
+        _ => Err(anyhow::anyhow!(
+            "Unknown engine '{}'. Supported engines: openai, openai-responses, anthropic, google, ollama",
+            engine_name
+        )),
+    }
 }
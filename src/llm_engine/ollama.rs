@@ -0,0 +1,382 @@
+use super::conversation::{Content, Message, Role};
+use super::error::EngineError;
+use super::http::read_json;
+use super::{record_exchange, EngineBackend, EngineState, Tool, ToolCall, ToolOutput, Usage};
+use crate::util::{option_or_env_fallback, OptionMap};
+use anyhow::Result;
+use log::{debug, warn};
+use serde_json::json;
+use serde_json::Value as json;
+use std::io::{BufRead, BufReader, Read};
+use std::time::Instant;
+
+/// How tools are offered to the model
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ToolMode {
+    /// Try native tool calling, switching to prompt emulation if the model doesn't support it
+    Auto,
+    /// Only use Ollama's native `tools` support
+    Native,
+    /// Describe the tools in the prompt and parse a JSON tool call out of the reply
+    Prompt,
+}
+
+/// Talks to Ollama's native `/api/chat` endpoint, for running against a local model
+pub struct Ollama {
+    model: String,
+    base_url: String,
+    tool_mode: ToolMode,
+    emulate_tools: bool,
+    state: EngineState,
+}
+
+impl Ollama {
+    pub fn new(options: &OptionMap) -> Self {
+        let base_url = option_or_env_fallback(options, "base_url", "OLLAMA_HOST", "http://localhost:11434");
+        let model = options.get("model").unwrap().to_string();
+        let tool_mode = match options.get("tool_mode").map(|v| v.as_str()) {
+            Some("native") => ToolMode::Native,
+            Some("prompt") => ToolMode::Prompt,
+            _ => ToolMode::Auto,
+        };
+
+        Self {
+            model,
+            base_url,
+            tool_mode,
+            emulate_tools: tool_mode == ToolMode::Prompt,
+            state: EngineState::new(options),
+        }
+    }
+
+    fn ollama_tool_definition(tool: &Tool) -> json {
+        json!({
+            "type": "function",
+            "function": {
+                "name": tool.definition["name"],
+                "description": tool.definition["description"],
+                "parameters": tool.definition["parameters"],
+            }
+        })
+    }
+
+    /// Instructions that stand in for native tool support
+    fn emulated_tools_prompt(&self) -> String {
+        let tool_descriptions = self
+            .state
+            .tools
+            .iter()
+            .map(|tool| {
+                format!(
+                    "- {}: {}\n  Arguments (JSON schema): {}",
+                    tool.name,
+                    tool.definition["description"].as_str().unwrap_or_default(),
+                    tool.definition["parameters"]
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        format!(
+            "You have access to the following tools:\n{}\n\n\
+            To use a tool, reply with ONLY a JSON object of the form \
+            {{\"tool\": \"<tool name>\", \"arguments\": {{...}}}} and nothing else. \
+            You must always respond by calling one of the tools.",
+            tool_descriptions
+        )
+    }
+
+    fn ollama_messages(message: &Message, emulate_tools: bool) -> Vec<json> {
+        let role = match message.role {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        };
+
+        let mut messages = Vec::new();
+        let mut text = Vec::new();
+        let mut images = Vec::new();
+        let mut tool_calls = Vec::new();
+
+        for item in &message.content {
+            match item {
+                Content::Text(content) => text.push(content.clone()),
+                Content::Image(base64_image) => images.push(base64_image.clone()),
+                Content::ToolCall { name, arguments, .. } => {
+                    if emulate_tools {
+                        text.push(json!({ "tool": name, "arguments": arguments }).to_string());
+                    } else {
+                        tool_calls.push(json!({
+                            "function": {
+                                "name": name,
+                                "arguments": arguments,
+                            }
+                        }));
+                    }
+                }
+                Content::ToolResult { name, output, .. } => {
+                    let result = match output {
+                        ToolOutput::Text(text) => text.clone(),
+                        ToolOutput::Error(error) => format!("Error: {}", error),
+                        ToolOutput::Image(base64_image) => {
+                            images.push(base64_image.clone());
+                            "The resulting image is attached.".to_string()
+                        }
+                    };
+                    if emulate_tools {
+                        text.push(format!("Result of tool {}:\n{}", name, result));
+                    } else {
+                        messages.push(json!({
+                            "role": "tool",
+                            "tool_name": name,
+                            "content": result,
+                        }));
+                    }
+                }
+                Content::Thinking { .. } => {}
+            }
+        }
+
+        if !text.is_empty() || !images.is_empty() || !tool_calls.is_empty() {
+            let mut ollama_message = json!({
+                "role": role,
+                "content": text.join("\n"),
+            });
+            if !images.is_empty() {
+                ollama_message["images"] = json!(images);
+            }
+            if !tool_calls.is_empty() {
+                ollama_message["tool_calls"] = json!(tool_calls);
+            }
+            messages.push(ollama_message);
+        }
+        messages
+    }
+
+    fn request_body(&self) -> json {
+        let mut messages = Vec::new();
+        if self.emulate_tools {
+            messages.push(json!({
+                "role": "system",
+                "content": self.emulated_tools_prompt(),
+            }));
+        }
+        messages.extend(
+            self.state
+                .conversation
+                .request_messages()
+                .iter()
+                .flat_map(|message| Self::ollama_messages(message, self.emulate_tools)),
+        );
+
+        let mut body = json!({
+            "model": self.model,
+            "messages": messages,
+            "stream": self.state.stream,
+        });
+        if !self.emulate_tools {
+            body["tools"] = json!(self.state.tools.iter().map(Self::ollama_tool_definition).collect::<Vec<_>>());
+        }
+        body
+    }
+
+    fn post_request(&mut self) -> Result<json> {
+        let body = self.request_body();
+
+        // print body for debugging
+        debug!("Request: {}", body);
+        let started = Instant::now();
+        let mut response = self
+            .state
+            .post(&format!("{}/api/chat", self.base_url), &[("Content-Type", "application/json")], &body)?;
+
+        let json = if self.state.stream {
+            self.read_stream(response.body_mut().as_reader())?
+        } else {
+            read_json(&mut response)?
+        };
+        debug!("Response: {}", json);
+        record_exchange(&mut self.state.exchange_callback, &body, &json, started);
+        Ok(json)
+    }
+
+    /// Ollama streams one JSON object per line; glue them back into a single message
+    fn read_stream(&mut self, reader: impl Read) -> Result<json> {
+        let mut content = String::new();
+        let mut tool_calls = Vec::new();
+        let mut last_chunk = json!({});
+
+        for line in BufReader::new(reader).lines() {
+            self.state.check_cancelled()?;
+            let line = line.map_err(|e| EngineError::Network(e.to_string()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let chunk = serde_json::from_str::<json>(&line).map_err(|e| EngineError::MalformedResponse(format!("Invalid JSON in stream: {}", e)))?;
+            if !chunk["error"].is_null() {
+                return Err(EngineError::from_api_error(&chunk).into());
+            }
+
+            if let Some(text) = chunk["message"]["content"].as_str() {
+                content.push_str(text);
+                if let Some(progress) = &mut self.state.progress {
+                    progress(text);
+                }
+            }
+            if let Some(calls) = chunk["message"]["tool_calls"].as_array() {
+                tool_calls.extend(calls.iter().cloned());
+            }
+            let done = chunk["done"].as_bool() == Some(true);
+            last_chunk = chunk;
+            if done {
+                break;
+            }
+        }
+
+        // Keep the metadata (like token counts) from the final chunk
+        last_chunk["message"] = json!({
+            "role": "assistant",
+            "content": content,
+            "tool_calls": tool_calls,
+        });
+        Ok(last_chunk)
+    }
+
+    /// Find the first JSON object in the text that looks like a tool call
+    fn parse_emulated_tool_call(text: &str) -> Option<(String, json)> {
+        for (start, _) in text.match_indices('{') {
+            let Some(Ok(value)) = serde_json::Deserializer::from_str(&text[start..]).into_iter::<json>().next() else {
+                continue;
+            };
+            let name = value["tool"].as_str().or_else(|| value["name"].as_str());
+            if let Some(name) = name {
+                let arguments = if value["arguments"].is_object() {
+                    value["arguments"].clone()
+                } else if value["parameters"].is_object() {
+                    value["parameters"].clone()
+                } else {
+                    json!({})
+                };
+                return Some((name.to_string(), arguments));
+            }
+        }
+        None
+    }
+}
+
+impl EngineBackend for Ollama {
+    fn state(&self) -> &EngineState {
+        &self.state
+    }
+
+    fn state_mut(&mut self) -> &mut EngineState {
+        &mut self.state
+    }
+
+    fn send_request(&mut self) -> Result<json> {
+        match self.post_request() {
+            Err(e) if self.tool_mode == ToolMode::Auto && !self.emulate_tools && matches!(e.downcast_ref(), Some(EngineError::ToolsUnsupported(_))) => {
+                warn!("Model {} has no native tool support, describing the tools in the prompt instead", self.model);
+                self.emulate_tools = true;
+                self.post_request()
+            }
+            response => response,
+        }
+    }
+
+    fn parse_response(&self, response: &json, step: u32) -> Result<(Vec<Content>, Vec<ToolCall>)> {
+        let message = &response["message"];
+        let mut assistant_content = Vec::new();
+        let mut tool_calls = Vec::new();
+        let text = message["content"].as_str().unwrap_or_default();
+
+        if let Some(thinking) = message["thinking"].as_str() {
+            debug!("Thinking: {}", thinking);
+        }
+
+        if self.emulate_tools {
+            match Self::parse_emulated_tool_call(text) {
+                Some((name, arguments)) => tool_calls.push(ToolCall {
+                    id: format!("call_{}", step),
+                    name,
+                    arguments,
+                }),
+                None => {
+                    debug!("Text: {}", text);
+                    assistant_content.push(Content::Text(text.to_string()));
+                }
+            }
+        } else {
+            if !text.is_empty() {
+                debug!("Text: {}", text);
+                assistant_content.push(Content::Text(text.to_string()));
+            }
+            for (index, tool_call) in message["tool_calls"].as_array().unwrap_or(&Vec::new()).iter().enumerate() {
+                let arguments = match &tool_call["function"]["arguments"] {
+                    // Some models hand back the arguments as a JSON string
+                    json::String(raw) => serde_json::from_str(raw).unwrap_or(json!({})),
+                    arguments => arguments.clone(),
+                };
+                tool_calls.push(ToolCall {
+                    id: format!("call_{}_{}", step, index),
+                    name: tool_call["function"]["name"].as_str().unwrap_or_default().to_string(),
+                    arguments,
+                });
+            }
+        }
+
+        for tool_call in &tool_calls {
+            assistant_content.push(Content::ToolCall {
+                id: tool_call.id.clone(),
+                name: tool_call.name.clone(),
+                arguments: tool_call.arguments.clone(),
+            });
+        }
+
+        Ok((assistant_content, tool_calls))
+    }
+
+    fn parse_usage(&self, response: &json) -> Usage {
+        Usage {
+            input_tokens: response["prompt_eval_count"].as_u64().unwrap_or(0),
+            output_tokens: response["eval_count"].as_u64().unwrap_or(0),
+            thinking_tokens: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_bare_tool_call() {
+        let (name, arguments) = Ollama::parse_emulated_tool_call(r#"{"tool": "draw_text", "arguments": {"text": "hi"}}"#).unwrap();
+        assert_eq!(name, "draw_text");
+        assert_eq!(arguments, json!({ "text": "hi" }));
+    }
+
+    #[test]
+    fn parses_a_tool_call_wrapped_in_prose() {
+        let text = "Sure, here you go:\n```json\n{\"name\": \"draw_svg\", \"parameters\": {\"svg\": \"<svg/>\"}}\n```\nDone.";
+        let (name, arguments) = Ollama::parse_emulated_tool_call(text).unwrap();
+        assert_eq!(name, "draw_svg");
+        assert_eq!(arguments, json!({ "svg": "<svg/>" }));
+    }
+
+    #[test]
+    fn skips_objects_that_are_not_tool_calls() {
+        let text = r#"The page says {"note": 1}. {"tool": "draw_text"}"#;
+        assert_eq!(Ollama::parse_emulated_tool_call(text), Some(("draw_text".to_string(), json!({}))));
+        assert_eq!(Ollama::parse_emulated_tool_call("No tools today {not json"), None);
+    }
+
+    #[test]
+    fn tools_unsupported_is_classified_from_the_400_body() {
+        let body = r#"{"error":"registry.ollama.ai/library/llama3.2-vision:latest does not support tools"}"#;
+        assert!(matches!(EngineError::from_status(400, body, None), EngineError::ToolsUnsupported(_)));
+        assert!(matches!(
+            EngineError::from_status(400, r#"{"error":"bad request"}"#, None),
+            EngineError::Http { status: 400, .. }
+        ));
+    }
+}
@@ -1,25 +1,37 @@
-use super::LLMEngine;
+use super::conversation::{Content, Message, Role};
+use super::error::EngineError;
+use super::http::read_json;
+use super::sse::read_events;
+use super::{record_exchange, EngineBackend, EngineState, Tool, ToolCall, ToolOutput, Usage};
 use crate::util::{option_or_env, option_or_env_fallback, OptionMap};
 use anyhow::Result;
-use log::{debug, info};
+use log::debug;
 use serde_json::json;
 use serde_json::Value as json;
-
-pub struct Tool {
-    name: String,
-    definition: json,
-    callback: Option<Box<dyn FnMut(json)>>,
-}
+use std::io::Read;
+use std::time::Instant;
 
 pub struct OpenAI {
     model: String,
     base_url: String,
     api_key: String,
-    tools: Vec<Tool>,
-    content: Vec<json>,
+    state: EngineState,
 }
 
 impl OpenAI {
+    pub fn new(options: &OptionMap) -> Self {
+        let api_key = option_or_env(options, "api_key", "OPENAI_API_KEY");
+        let base_url = option_or_env_fallback(options, "base_url", "OPENAI_BASE_URL", "https://api.openai.com");
+        let model = options.get("model").unwrap().to_string();
+
+        Self {
+            model,
+            base_url,
+            api_key,
+            state: EngineState::new(options),
+        }
+    }
+
     fn openai_tool_definition(tool: &Tool) -> json {
         json!({
                 "type": "function",
@@ -31,106 +43,235 @@ impl OpenAI {
         })
     }
 
-    pub fn add_content(&mut self, content: json) {
-        self.content.push(content);
+    fn openai_messages(message: &Message) -> Vec<json> {
+        match message.role {
+            Role::User => {
+                // Tool results are their own messages, and must directly follow the assistant tool call
+                let mut messages = Vec::new();
+                let mut content = Vec::new();
+                for item in &message.content {
+                    match item {
+                        Content::Text(text) => content.push(json!({
+                            "type": "text",
+                            "text": text,
+                        })),
+                        Content::Image(base64_image) => content.push(json!({
+                            "type": "image_url",
+                            "image_url": {
+                                "url": format!("data:image/png;base64,{}", base64_image)
+                            }
+                        })),
+                        Content::ToolResult { id, output, .. } => {
+                            let text = match output {
+                                ToolOutput::Text(text) => text.clone(),
+                                ToolOutput::Error(error) => format!("Error: {}", error),
+                                ToolOutput::Image(base64_image) => {
+                                    // Tool messages can only carry text, so the image rides along in the user message
+                                    content.push(json!({
+                                        "type": "image_url",
+                                        "image_url": {
+                                            "url": format!("data:image/png;base64,{}", base64_image)
+                                        }
+                                    }));
+                                    "The resulting image is attached to the next message.".to_string()
+                                }
+                            };
+                            messages.push(json!({
+                                "role": "tool",
+                                "tool_call_id": id,
+                                "content": text,
+                            }));
+                        }
+                        Content::Thinking { .. } | Content::ToolCall { .. } => {}
+                    }
+                }
+                if !content.is_empty() {
+                    messages.push(json!({
+                        "role": "user",
+                        "content": content,
+                    }));
+                }
+                messages
+            }
+            Role::Assistant => {
+                let text = message
+                    .content
+                    .iter()
+                    .filter_map(|item| match item {
+                        Content::Text(text) => Some(text.as_str()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let tool_calls = message
+                    .content
+                    .iter()
+                    .filter_map(|item| match item {
+                        Content::ToolCall { id, name, arguments } => Some(json!({
+                            "id": id,
+                            "type": "function",
+                            "function": {
+                                "name": name,
+                                "arguments": arguments.to_string(),
+                            }
+                        })),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                let mut assistant = json!({
+                    "role": "assistant",
+                    "content": if text.is_empty() { json!(null) } else { json!(text) },
+                });
+                if !tool_calls.is_empty() {
+                    assistant["tool_calls"] = json!(tool_calls);
+                }
+                vec![assistant]
+            }
+        }
     }
-}
 
-impl LLMEngine for OpenAI {
-    fn new(options: &OptionMap) -> Self {
-        let api_key = option_or_env(options, "api_key", "OPENAI_API_KEY");
-        let base_url = option_or_env_fallback(options, "base_url", "OPENAI_BASE_URL", "https://api.openai.com");
-        let model = options.get("model").unwrap().to_string();
+    /// Reassemble a streamed response into the same shape as a regular one
+    fn read_stream(&mut self, reader: impl Read) -> Result<json> {
+        let mut content = String::new();
+        let mut tool_calls: Vec<json> = Vec::new();
+        let mut usage = json!(null);
+        let progress = &mut self.state.progress;
 
-        Self {
-            model,
-            base_url,
-            api_key,
-            tools: Vec::new(),
-            content: Vec::new(),
-        }
-    }
+        read_events(reader, &self.state.cancel, |_event, data| {
+            if !data["error"].is_null() {
+                return Err(EngineError::from_api_error(&data["error"]).into());
+            }
 
-    fn register_tool(&mut self, name: &str, definition: json, callback: Box<dyn FnMut(json)>) {
-        self.tools.push(Tool {
-            name: name.to_string(),
-            definition,
-            callback: Some(callback),
-        });
-    }
+            // The last chunk has no choices, only the usage
+            if !data["usage"].is_null() {
+                usage = data["usage"].clone();
+            }
 
-    fn add_text_content(&mut self, text: &str) {
-        self.add_content(json!({
-            "type": "text",
-            "text": text,
-        }));
-    }
+            let delta = &data["choices"][0]["delta"];
+            if let Some(text) = delta["content"].as_str() {
+                content.push_str(text);
+                if let Some(progress) = progress {
+                    progress(text);
+                }
+            }
 
-    fn add_image_content(&mut self, base64_image: &str) {
-        self.add_content(json!({
-            "type": "image_url",
-            "image_url": {
-                "url": format!("data:image/png;base64,{}", base64_image)
+            for tool_call_delta in delta["tool_calls"].as_array().unwrap_or(&Vec::new()) {
+                let index = tool_call_delta["index"].as_u64().unwrap_or(0) as usize;
+                while tool_calls.len() <= index {
+                    tool_calls.push(json!({ "type": "function", "function": { "name": "", "arguments": "" } }));
+                }
+                let tool_call = &mut tool_calls[index];
+                if let Some(id) = tool_call_delta["id"].as_str() {
+                    tool_call["id"] = json!(id);
+                }
+                if let Some(name) = tool_call_delta["function"]["name"].as_str() {
+                    let joined = format!("{}{}", tool_call["function"]["name"].as_str().unwrap_or(""), name);
+                    tool_call["function"]["name"] = json!(joined);
+                }
+                if let Some(arguments) = tool_call_delta["function"]["arguments"].as_str() {
+                    let joined = format!("{}{}", tool_call["function"]["arguments"].as_str().unwrap_or(""), arguments);
+                    tool_call["function"]["arguments"] = json!(joined);
+                    if let Some(progress) = progress {
+                        progress(arguments);
+                    }
+                }
             }
-        }));
+            Ok(())
+        })?;
+
+        Ok(json!({
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": if content.is_empty() { json!(null) } else { json!(content) },
+                    "tool_calls": tool_calls,
+                }
+            }],
+            "usage": usage,
+        }))
+    }
+}
+
+impl EngineBackend for OpenAI {
+    fn state(&self) -> &EngineState {
+        &self.state
     }
 
-    fn clear_content(&mut self) {
-        self.content.clear();
+    fn state_mut(&mut self) -> &mut EngineState {
+        &mut self.state
     }
 
-    fn execute(&mut self) -> Result<()> {
-        let body = json!({
+    fn send_request(&mut self) -> Result<json> {
+        let mut body = json!({
             "model": self.model,
-            "messages": [{
-                "role": "user",
-                "content": self.content
-            }],
-            "tools": self.tools.iter().map(Self::openai_tool_definition).collect::<Vec<_>>(),
+            "messages": self.state.conversation.request_messages().iter().flat_map(Self::openai_messages).collect::<Vec<_>>(),
+            "tools": self.state.tools.iter().map(Self::openai_tool_definition).collect::<Vec<_>>(),
             "tool_choice": "required",
             "parallel_tool_calls": false
         });
 
+        if self.state.stream {
+            body["stream"] = json!(true);
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+
         // print body for debugging
         debug!("Request: {}", body);
-        let raw_response = ureq::post(format!("{}/v1/chat/completions", self.base_url).as_str())
-            .header("Authorization", &format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .send_json(&body);
-
-        let mut response = match raw_response {
-            Ok(response) => response,
-            Err(err) => {
-                info!("API Error: {}", err);
-                return Err(anyhow::anyhow!("API ERROR: {}", err));
-            }
-        };
+        let authorization = format!("Bearer {}", self.api_key);
+        let started = Instant::now();
+        let mut response = self.state.post(
+            &format!("{}/v1/chat/completions", self.base_url),
+            &[("Authorization", authorization.as_str()), ("Content-Type", "application/json")],
+            &body,
+        )?;
 
-        // Read response body as string
-        let body_text = response.body_mut().read_to_string().unwrap();
-        let json: json = serde_json::from_str(&body_text).unwrap();
+        let json = if self.state.stream {
+            self.read_stream(response.body_mut().as_reader())?
+        } else {
+            read_json(&mut response)?
+        };
         debug!("Response: {}", json);
+        record_exchange(&mut self.state.exchange_callback, &body, &json, started);
+        Ok(json)
+    }
 
-        let tool_calls = &json["choices"][0]["message"]["tool_calls"];
+    fn parse_response(&self, response: &json, _step: u32) -> Result<(Vec<Content>, Vec<ToolCall>)> {
+        let message = &response["choices"][0]["message"];
+        let mut assistant_content = Vec::new();
+        let mut tool_calls = Vec::new();
 
-        if let Some(tool_call) = tool_calls.get(0) {
-            let function_name = tool_call["function"]["name"].as_str().unwrap();
-            let function_input_raw = tool_call["function"]["arguments"].as_str().unwrap();
-            let function_input = serde_json::from_str::<json>(function_input_raw).unwrap();
-            let tool = self.tools.iter_mut().find(|tool| tool.name == function_name);
+        if let Some(text) = message["content"].as_str() {
+            debug!("Text: {}", text);
+            assistant_content.push(Content::Text(text.to_string()));
+        }
 
-            if let Some(tool) = tool {
-                if let Some(callback) = &mut tool.callback {
-                    callback(function_input.clone());
-                    Ok(())
-                } else {
-                    Err(anyhow::anyhow!("No callback registered for tool {}", function_name))
-                }
-            } else {
-                Err(anyhow::anyhow!("No tool registered with name {}", function_name))
-            }
-        } else {
-            Err(anyhow::anyhow!("No tool calls found in response"))
+        for tool_call in message["tool_calls"].as_array().unwrap_or(&Vec::new()) {
+            let id = tool_call["id"].as_str().unwrap_or_default().to_string();
+            let function_name = tool_call["function"]["name"].as_str().unwrap_or_default().to_string();
+            let function_input_raw = tool_call["function"]["arguments"].as_str().unwrap_or("{}");
+            let function_input = serde_json::from_str::<json>(function_input_raw)
+                .map_err(|e| EngineError::MalformedResponse(format!("Tool arguments for {} are not valid JSON: {}", function_name, e)))?;
+            assistant_content.push(Content::ToolCall {
+                id: id.clone(),
+                name: function_name.clone(),
+                arguments: function_input.clone(),
+            });
+            tool_calls.push(ToolCall {
+                id,
+                name: function_name,
+                arguments: function_input,
+            });
+        }
+
+        Ok((assistant_content, tool_calls))
+    }
+
+    fn parse_usage(&self, response: &json) -> Usage {
+        let usage = &response["usage"];
+        Usage {
+            input_tokens: usage["prompt_tokens"].as_u64().unwrap_or(0),
+            output_tokens: usage["completion_tokens"].as_u64().unwrap_or(0),
+            thinking_tokens: usage["completion_tokens_details"]["reasoning_tokens"].as_u64().unwrap_or(0),
         }
     }
 }
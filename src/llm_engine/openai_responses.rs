@@ -0,0 +1,376 @@
+use super::conversation::{Content, Role};
+use super::error::EngineError;
+use super::http::read_json;
+use super::sse::read_events;
+use super::{record_exchange, EngineBackend, EngineState, Tool, ToolCall, ToolOutput, Usage};
+use crate::util::{option_or_env, option_or_env_fallback, OptionMap};
+use anyhow::Result;
+use log::debug;
+use serde_json::json;
+use serde_json::Value as json;
+use std::io::Read;
+use std::time::Instant;
+
+/// Talks to OpenAI's `/v1/responses` endpoint. Unlike chat completions this
+/// supports built-in tools (like web search), reasoning settings, and keeping
+/// the conversation on the server, so follow-up requests only send what's new.
+pub struct OpenAIResponses {
+    model: String,
+    base_url: String,
+    api_key: String,
+    builtin_tools: Vec<String>,
+    reasoning_effort: Option<String>,
+    stateful: bool,
+    previous_response_id: Option<String>,
+    // How many conversation content items the server already has from earlier requests
+    sent_items: usize,
+    state: EngineState,
+}
+
+impl OpenAIResponses {
+    pub fn new(options: &OptionMap) -> Self {
+        let api_key = option_or_env(options, "api_key", "OPENAI_API_KEY");
+        let base_url = option_or_env_fallback(options, "base_url", "OPENAI_BASE_URL", "https://api.openai.com");
+        let model = options.get("model").unwrap().to_string();
+        let stateful = options.get("stateful").is_none_or(|v| v == "true");
+        let reasoning_effort = options.get("reasoning_effort").cloned();
+
+        let mut builtin_tools = options
+            .get("builtin_tools")
+            .map(|v| {
+                v.split(',')
+                    .map(|tool| tool.trim().to_string())
+                    .filter(|tool| !tool.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if options.get("web_search").is_some_and(|v| v == "true") && !builtin_tools.iter().any(|tool| tool.starts_with("web_search")) {
+            builtin_tools.push("web_search".to_string());
+        }
+
+        Self {
+            model,
+            base_url,
+            api_key,
+            builtin_tools,
+            reasoning_effort,
+            stateful,
+            previous_response_id: None,
+            sent_items: 0,
+            state: EngineState::new(options),
+        }
+    }
+
+    fn function_tool_definition(tool: &Tool) -> json {
+        json!({
+            "type": "function",
+            "name": tool.definition["name"],
+            "description": tool.definition["description"],
+            "parameters": tool.definition["parameters"],
+        })
+    }
+
+    fn builtin_tool_definition(tool_type: &str) -> json {
+        match tool_type {
+            "web_search" | "web_search_preview" => json!({ "type": "web_search_preview" }),
+            "code_interpreter" => json!({ "type": "code_interpreter", "container": { "type": "auto" } }),
+            _ => json!({ "type": tool_type }),
+        }
+    }
+
+    fn input_image(base64_image: &str) -> json {
+        json!({
+            "type": "input_image",
+            "image_url": format!("data:image/png;base64,{}", base64_image),
+        })
+    }
+
+    /// Turn conversation content into Responses API input items. Consecutive
+    /// user text and images are gathered into a single message item. Reasoning
+    /// is only replayed by id when `with_reasoning`, since the ids only point at
+    /// something when the server kept the earlier responses.
+    fn input_items(content: &[(Role, &Content)], with_reasoning: bool) -> Vec<json> {
+        let mut items = Vec::new();
+        let mut user_content = Vec::new();
+
+        for (role, item) in content {
+            if *role == Role::Assistant && !user_content.is_empty() {
+                items.push(json!({
+                    "role": "user",
+                    "content": std::mem::take(&mut user_content),
+                }));
+            }
+            match (role, item) {
+                (Role::User, Content::Text(text)) => user_content.push(json!({ "type": "input_text", "text": text })),
+                (Role::User, Content::Image(base64_image)) => user_content.push(Self::input_image(base64_image)),
+                (_, Content::ToolResult { id, output, .. }) => {
+                    let text = match output {
+                        ToolOutput::Text(text) => text.clone(),
+                        ToolOutput::Error(error) => format!("Error: {}", error),
+                        ToolOutput::Image(base64_image) => {
+                            // Function outputs can only carry text, so the image rides along in the user message
+                            user_content.push(Self::input_image(base64_image));
+                            "The resulting image is attached to the next message.".to_string()
+                        }
+                    };
+                    items.push(json!({
+                        "type": "function_call_output",
+                        "call_id": id,
+                        "output": text,
+                    }));
+                }
+                (Role::Assistant, Content::Text(text)) => items.push(json!({
+                    "role": "assistant",
+                    "content": [{ "type": "output_text", "text": text }],
+                })),
+                (Role::Assistant, Content::Thinking { signature, .. }) if with_reasoning => items.push(json!({
+                    "type": "reasoning",
+                    "id": signature,
+                    "summary": [],
+                })),
+                (Role::Assistant, Content::ToolCall { id, name, arguments }) => items.push(json!({
+                    "type": "function_call",
+                    "call_id": id,
+                    "name": name,
+                    "arguments": arguments.to_string(),
+                })),
+                _ => {}
+            }
+        }
+
+        if !user_content.is_empty() {
+            items.push(json!({
+                "role": "user",
+                "content": user_content,
+            }));
+        }
+        items
+    }
+
+    fn request_body(&self) -> json {
+        let messages = self.state.conversation.request_messages();
+        let content = messages
+            .iter()
+            .flat_map(|message| message.content.iter().map(move |item| (message.role, item)))
+            .collect::<Vec<_>>();
+
+        // With a previous response on the server, only send what it hasn't seen yet
+        let (input, previous_response_id) = match &self.previous_response_id {
+            Some(id) if self.stateful => (Self::input_items(&content[self.sent_items.min(content.len())..], true), json!(id)),
+            _ => (Self::input_items(&content, self.stateful), json!(null)),
+        };
+
+        let mut tools = self.state.tools.iter().map(Self::function_tool_definition).collect::<Vec<_>>();
+        tools.extend(self.builtin_tools.iter().map(|tool_type| Self::builtin_tool_definition(tool_type)));
+
+        let mut body = json!({
+            "model": self.model,
+            "input": input,
+            "tools": tools,
+            // Requiring a call would force one even when a built-in tool like web search answers the question
+            "tool_choice": if self.builtin_tools.is_empty() { "required" } else { "auto" },
+            "parallel_tool_calls": false,
+        });
+
+        if !previous_response_id.is_null() {
+            body["previous_response_id"] = previous_response_id;
+        }
+
+        if let Some(effort) = &self.reasoning_effort {
+            body["reasoning"] = json!({
+                "effort": effort,
+                "summary": "auto",
+            });
+        }
+
+        if self.state.stream {
+            body["stream"] = json!(true);
+        }
+
+        body
+    }
+
+    /// The final streamed event carries the whole response, the deltas before it are only for progress
+    fn read_stream(&mut self, reader: impl Read) -> Result<json> {
+        let mut completed = None;
+        let progress = &mut self.state.progress;
+
+        read_events(reader, &self.state.cancel, |event, data| {
+            let event_type = data["type"].as_str().unwrap_or(event);
+            match event_type {
+                "response.output_text.delta" | "response.function_call_arguments.delta" | "response.reasoning_summary_text.delta" => {
+                    if let (Some(progress), Some(delta)) = (progress.as_mut(), data["delta"].as_str()) {
+                        progress(delta);
+                    }
+                }
+                "response.completed" => completed = Some(data["response"].clone()),
+                "response.failed" | "response.incomplete" => {
+                    return Err(EngineError::from_api_error(&data["response"]["error"]).into());
+                }
+                "error" => return Err(EngineError::from_api_error(data).into()),
+                _ => {}
+            }
+            Ok(())
+        })?;
+
+        Ok(completed.ok_or_else(|| EngineError::MalformedResponse("Stream ended without a completed response".to_string()))?)
+    }
+
+    fn content_count(&self) -> usize {
+        self.state.conversation.messages().iter().map(|message| message.content.len()).sum()
+    }
+}
+
+impl EngineBackend for OpenAIResponses {
+    fn state(&self) -> &EngineState {
+        &self.state
+    }
+
+    fn state_mut(&mut self) -> &mut EngineState {
+        &mut self.state
+    }
+
+    fn send_request(&mut self) -> Result<json> {
+        let body = self.request_body();
+
+        // print body for debugging
+        debug!("Request: {}", body);
+        let authorization = format!("Bearer {}", self.api_key);
+        let started = Instant::now();
+        let mut response = self.state.post(
+            &format!("{}/v1/responses", self.base_url),
+            &[("Authorization", authorization.as_str()), ("Content-Type", "application/json")],
+            &body,
+        )?;
+
+        let json = if self.state.stream {
+            self.read_stream(response.body_mut().as_reader())?
+        } else {
+            read_json(&mut response)?
+        };
+        debug!("Response: {}", json);
+        record_exchange(&mut self.state.exchange_callback, &body, &json, started);
+        Ok(json)
+    }
+
+    fn parse_response(&self, response: &json, _step: u32) -> Result<(Vec<Content>, Vec<ToolCall>)> {
+        if !response["error"].is_null() {
+            return Err(EngineError::from_api_error(&response["error"]).into());
+        }
+
+        let mut assistant_content = Vec::new();
+        let mut tool_calls = Vec::new();
+
+        for item in response["output"].as_array().unwrap_or(&Vec::new()) {
+            match item["type"].as_str().unwrap_or_default() {
+                "reasoning" => {
+                    let summary = item["summary"]
+                        .as_array()
+                        .unwrap_or(&Vec::new())
+                        .iter()
+                        .filter_map(|part| part["text"].as_str())
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    debug!("Reasoning: {}", summary);
+                    assistant_content.push(Content::Thinking {
+                        thinking: summary,
+                        signature: item["id"].as_str().unwrap_or_default().to_string(),
+                    });
+                }
+                "message" => {
+                    for part in item["content"].as_array().unwrap_or(&Vec::new()) {
+                        if let Some(text) = part["text"].as_str() {
+                            debug!("Text: {}", text);
+                            assistant_content.push(Content::Text(text.to_string()));
+                        }
+                    }
+                }
+                "function_call" => {
+                    let id = item["call_id"].as_str().unwrap_or_default().to_string();
+                    let name = item["name"].as_str().unwrap_or_default().to_string();
+                    let arguments = serde_json::from_str::<json>(item["arguments"].as_str().unwrap_or("{}"))
+                        .map_err(|e| EngineError::MalformedResponse(format!("Tool arguments for {} are not valid JSON: {}", name, e)))?;
+                    assistant_content.push(Content::ToolCall {
+                        id: id.clone(),
+                        name: name.clone(),
+                        arguments: arguments.clone(),
+                    });
+                    tool_calls.push(ToolCall { id, name, arguments });
+                }
+                other => debug!("Built-in tool output ({}): {}", other, item),
+            }
+        }
+
+        Ok((assistant_content, tool_calls))
+    }
+
+    fn parse_usage(&self, response: &json) -> Usage {
+        let usage = &response["usage"];
+        Usage {
+            input_tokens: usage["input_tokens"].as_u64().unwrap_or(0),
+            output_tokens: usage["output_tokens"].as_u64().unwrap_or(0),
+            thinking_tokens: usage["output_tokens_details"]["reasoning_tokens"].as_u64().unwrap_or(0),
+        }
+    }
+
+    /// The server now holds everything up to and including this response
+    fn response_committed(&mut self, response: &json) {
+        if self.stateful {
+            self.previous_response_id = response["id"].as_str().map(|id| id.to_string());
+            self.sent_items = self.content_count();
+        }
+    }
+
+    fn history_cleared(&mut self) {
+        self.previous_response_id = None;
+        self.sent_items = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine(options: &[(&str, &str)]) -> OpenAIResponses {
+        let mut map = OptionMap::new();
+        map.insert("model".to_string(), "gpt-5".to_string());
+        map.insert("api_key".to_string(), "test".to_string());
+        for (key, value) in options {
+            map.insert(key.to_string(), value.to_string());
+        }
+        OpenAIResponses::new(&map)
+    }
+
+    #[test]
+    fn only_requires_a_tool_call_without_builtin_tools() {
+        assert_eq!(engine(&[]).request_body()["tool_choice"], "required");
+        assert_eq!(engine(&[("web_search", "true")]).request_body()["tool_choice"], "auto");
+    }
+
+    #[test]
+    fn replays_reasoning_only_when_the_server_kept_it() {
+        let with_history = |stateful: &str| {
+            let mut engine = engine(&[("stateful", stateful)]);
+            let conversation = &mut engine.state.conversation;
+            conversation.add_content(Content::Text("Page".to_string()));
+            conversation.commit_user_turn();
+            conversation.add_assistant_turn(vec![
+                Content::Thinking {
+                    thinking: String::new(),
+                    signature: "rs_1".to_string(),
+                },
+                Content::Text("Hello".to_string()),
+            ]);
+            conversation.add_content(Content::Text("Next page".to_string()));
+            engine.request_body()["input"].as_array().unwrap().clone()
+        };
+
+        let stateless = with_history("false");
+        assert_eq!(stateless.len(), 3);
+        assert!(stateless.iter().all(|item| item["type"] != "reasoning"));
+        // Without a previous response id a stateful engine sends it all, reasoning included
+        let stateful = with_history("true");
+        assert_eq!(stateful.len(), 4);
+        assert_eq!(stateful[1], json!({ "type": "reasoning", "id": "rs_1", "summary": [] }));
+    }
+}
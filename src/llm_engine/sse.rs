@@ -0,0 +1,117 @@
+use super::error::EngineError;
+use anyhow::Result;
+use log::trace;
+use serde_json::Value as json;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Read a server-sent event stream, calling `on_event` with the event name (if
+/// any) and the parsed JSON data of each event. Stops at the end of the stream,
+/// at a `[DONE]` marker, or with an error once `cancel` is set.
+pub fn read_events(reader: impl Read, cancel: &AtomicBool, mut on_event: impl FnMut(&str, &json) -> Result<()>) -> Result<()> {
+    let mut event_name = String::new();
+    let mut data = String::new();
+
+    for line in BufReader::new(reader).lines() {
+        if cancel.load(Ordering::SeqCst) {
+            return Err(EngineError::Cancelled.into());
+        }
+
+        let line = line.map_err(|e| EngineError::Network(e.to_string()))?;
+        trace!("SSE: {}", line);
+
+        if line.is_empty() {
+            if !data.is_empty() {
+                if data == "[DONE]" {
+                    return Ok(());
+                }
+                let parsed = serde_json::from_str::<json>(&data).map_err(|e| EngineError::MalformedResponse(format!("Invalid JSON in stream: {}", e)))?;
+                on_event(&event_name, &parsed)?;
+            }
+            event_name.clear();
+            data.clear();
+        } else if let Some(value) = line.strip_prefix("event:") {
+            event_name = value.trim().to_string();
+        } else if let Some(value) = line.strip_prefix("data:") {
+            if !data.is_empty() {
+                data.push('\n');
+            }
+            data.push_str(value.trim_start());
+        }
+    }
+
+    // Some servers end the stream without a trailing blank line
+    if !data.is_empty() && data != "[DONE]" {
+        let parsed = serde_json::from_str::<json>(&data).map_err(|e| EngineError::MalformedResponse(format!("Invalid JSON in stream: {}", e)))?;
+        on_event(&event_name, &parsed)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out the stream a few bytes at a time, the way it arrives over the network
+    struct Chunked<'a> {
+        data: &'a [u8],
+        chunk_size: usize,
+    }
+
+    impl Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let size = self.chunk_size.min(buf.len()).min(self.data.len());
+            buf[..size].copy_from_slice(&self.data[..size]);
+            self.data = &self.data[size..];
+            Ok(size)
+        }
+    }
+
+    fn collect(stream: &str, chunk_size: usize) -> Vec<(String, json)> {
+        let mut events = Vec::new();
+        let reader = Chunked {
+            data: stream.as_bytes(),
+            chunk_size,
+        };
+        read_events(reader, &AtomicBool::new(false), |event, data| {
+            events.push((event.to_string(), data.clone()));
+            Ok(())
+        })
+        .unwrap();
+        events
+    }
+
+    #[test]
+    fn reassembles_events_split_across_chunks() {
+        let stream = "event: message_start\ndata: {\"type\":\"message_start\"}\n\n\
+                      event: content_block_delta\r\ndata: {\"type\":\"content_block_delta\",\"delta\":{\"text\":\"hé\"}}\r\n\r\n";
+        for chunk_size in [1, 3, 7, 64] {
+            let events = collect(stream, chunk_size);
+            assert_eq!(events.len(), 2, "chunk size {}", chunk_size);
+            assert_eq!(events[0].0, "message_start");
+            assert_eq!(events[1].0, "content_block_delta");
+            assert_eq!(events[1].1["delta"]["text"], "hé");
+        }
+    }
+
+    #[test]
+    fn joins_multi_line_data_and_stops_at_done() {
+        let stream = "data: {\"a\":\ndata: 1}\n\ndata: [DONE]\n\ndata: {\"after\":true}\n\n";
+        let events = collect(stream, 5);
+        assert_eq!(events, [(String::new(), serde_json::json!({ "a": 1 }))]);
+    }
+
+    #[test]
+    fn reads_a_last_event_without_a_blank_line() {
+        let events = collect("data: {\"done\":true}", 4);
+        assert_eq!(events, [(String::new(), serde_json::json!({ "done": true }))]);
+    }
+
+    #[test]
+    fn stops_once_cancelled() {
+        let cancel = AtomicBool::new(true);
+        let result = read_events("data: {}\n\n".as_bytes(), &cancel, |_, _| Ok(()));
+        assert!(matches!(result.unwrap_err().downcast_ref(), Some(EngineError::Cancelled)));
+    }
+}
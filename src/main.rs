@@ -1,39 +1,94 @@
 use anyhow::Result;
 use base64::prelude::*;
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use dotenv::dotenv;
-use log::{debug, info};
+use log::{debug, info, warn};
 use serde::Serialize;
+use serde_json::json;
 use serde_json::Value as json;
+use std::cell::RefCell;
+use std::io::Read;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
-use std::thread::sleep;
-use std::time::Duration;
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
 
 use ghostwriter::{
-    config::Config,
+    config::{Config, FallbackModel, TriggerZone},
+    crop::NewInk,
     embedded_assets::load_config,
+    eval::{run_eval, EvalMatrix},
+    fill::{fill_strokes, FillMode},
+    gesture::{parse_gesture_actions, Gesture, GestureAction, GestureRecognizer, Trigger},
     keyboard::Keyboard,
-    llm_engine::{anthropic::Anthropic, google::Google, openai::OpenAI, LLMEngine},
+    llm_engine::{create_engine, determine_engine_name, error::EngineError, Exchange, LLMEngine, ToolCallback, ToolOutput, Usage},
+    mcp::load_mcp_tools,
     pen::Pen,
+    pen_trigger::PenTrigger,
+    plotter::svg_to_polylines,
     screenshot::Screenshot,
     segmenter::analyze_image,
-    touch::{Touch, TriggerCorner},
-    util::{setup_uinput, svg_to_bitmap, write_bitmap_to_file, OptionMap},
+    tools::{external_command_callback, load_tool_definition},
+    touch::{Touch, TriggerArea, TriggerCorner},
+    usage::{price_for, Ledger},
+    util::{changed_percent, is_same_page, setup_uinput, strip_images, svg_to_bitmap, write_bitmap_to_file, OptionMap},
 };
 
 // Output dimensions remain the same for both devices
 const VIRTUAL_WIDTH: u32 = 768;
 const VIRTUAL_HEIGHT: u32 = 1024;
 
-#[derive(Parser, Serialize)]
+/// How many pages --crop-new-ink remembers
+const MAX_PAGES: usize = 10;
+
+/// How long to wait before checking the daily budget again, when nothing else waits for a trigger
+const BUDGET_RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Parser)]
 #[command(author, version)]
 #[command(about = "Vision-LLM Agent for the reMarkable2")]
 #[command(
     long_about = "Ghostwriter is an exploration of how to interact with vision-LLM through the handwritten medium of the reMarkable2. It is a pluggable system; you can provide a custom prompt and custom 'tools' that the agent can use."
 )]
 #[command(after_help = "See https://github.com/awwaiid/ghostwriter for updates!")]
-pub struct Args {
+struct Cli {
+    /// Set the log level. Try 'debug' or 'trace'
+    #[arg(long, default_value = "info", global = true)]
+    log_level: String,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Without a subcommand, ghostwriter runs as with `run`
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Wait for the trigger and respond to the page, over and over (the default)
+    Run(RunArgs),
+    /// Respond to one screenshot or PNG file and exit
+    Once(OnceArgs),
+    /// Run the evaluation scenarios against a matrix of settings and write an HTML report
+    Eval(EvalArgs),
+    /// Show the configuration that would be used, or save it to ~/.ghostwriter.toml
+    Config(ConfigArgs),
+    /// Save a screenshot of the page as a PNG
+    Screenshot(ScreenshotArgs),
+    /// Draw an SVG file on the page
+    Draw(DrawArgs),
+    /// Type text onto the page with the virtual keyboard
+    Type(TypeArgs),
+}
+
+/// Which model to ask and how
+#[derive(clap::Args, Serialize)]
+struct ModelArgs {
     /// Sets the engine to use (openai, anthropic);
     /// Sometimes we can guess the engine from the model name
     #[arg(long)]
@@ -57,17 +112,93 @@ pub struct Args {
     #[arg(long, default_value = "general.json")]
     prompt: String,
 
-    /// Do not actually submit to the model, for testing
-    #[arg(short, long)]
-    no_submit: bool,
+    /// Disable SVG drawing tool
+    #[arg(long)]
+    no_svg: bool,
 
-    /// Skip running draw_text or draw_svg, for testing
+    /// Apply segmentation
     #[arg(long)]
-    no_draw: bool,
+    apply_segmentation: bool,
 
-    /// Disable SVG drawing tool
+    /// Send a crop of the ink added since the last response, plus the whole page at half resolution, instead of the full screenshot
     #[arg(long)]
-    no_svg: bool,
+    crop_new_ink: bool,
+
+    /// Stream the model response, showing progress as it arrives
+    #[arg(long)]
+    stream: bool,
+
+    /// Maximum number of model round-trips per trigger when tools feed results back
+    #[arg(long, default_value = "5")]
+    max_tool_steps: u32,
+
+    /// How many of the latest screenshots the conversation history keeps; older ones are left out of later requests
+    #[arg(long, default_value = "4")]
+    max_history_images: usize,
+
+    /// How many times to retry a model request that was rate limited, overloaded, or timed out
+    #[arg(long, default_value = "3")]
+    max_retries: u32,
+
+    /// Enable web search (for Anthropic and openai-responses engines)
+    #[arg(long)]
+    web_search: bool,
+
+    /// Enable model thinking (for Anthropic models)
+    #[arg(long)]
+    thinking: bool,
+
+    /// Set the thinking token budget (for Anthropic models)
+    #[arg(long, default_value = "5000")]
+    thinking_tokens: u32,
+
+    /// Reasoning effort for reasoning models (for the openai-responses engine): minimal, low, medium, or high
+    #[arg(long)]
+    reasoning_effort: Option<String>,
+
+    /// Comma separated built-in tools to enable (for the openai-responses engine), like web_search_preview,code_interpreter
+    #[arg(long)]
+    builtin_tools: Option<String>,
+
+    /// Send the whole conversation with every request instead of chaining from the previous response (for the openai-responses engine)
+    #[arg(long)]
+    no_stateful: bool,
+
+    /// How tools are offered to Ollama models: auto, native, or prompt (describe them in the prompt and parse the JSON reply)
+    #[arg(long, default_value = "auto")]
+    tool_mode: String,
+
+    /// Stop submitting to the model once this many US dollars have been spent today (see prices in the config file)
+    #[arg(long)]
+    daily_budget: Option<f64>,
+
+    /// Where to keep the running record of token usage and cost [default: ~/.ghostwriter_usage.jsonl]
+    #[arg(long)]
+    usage_ledger: Option<String>,
+
+    /// Record model responses to this cassette file, or replay them from it (see --cassette-mode)
+    #[arg(long)]
+    cassette: Option<String>,
+
+    /// record (call the provider and save each response) or replay (answer from the cassette, no network)
+    #[arg(long, default_value = "replay")]
+    cassette_mode: String,
+
+    /// Output file for the full model exchange (requests, responses, tool calls, timings, tokens)
+    #[arg(long)]
+    model_output_file: Option<String>,
+
+    /// Keep the screenshot bytes in --model-output-file instead of a placeholder
+    #[arg(long)]
+    model_output_include_images: bool,
+}
+
+/// How responses get onto the page
+#[derive(clap::Args, Serialize)]
+struct OutputArgs {
+    /// Skip running draw_text or draw_svg, for testing
+    #[arg(long)]
+    no_draw: bool,
 
     /// Disable keyboard
     #[arg(long)]
@@ -77,25 +208,48 @@ pub struct Args {
     #[arg(long)]
     no_draw_progress: bool,
 
-    /// Input PNG file for testing
-    #[arg(long)]
-    input_png: Option<String>,
+    /// How to draw SVGs: vector (pen strokes along the paths, outlining filled shapes) or raster (scan the rendered bitmap)
+    #[arg(long, default_value = "vector")]
+    svg_mode: String,
+
+    /// How raster mode fills dark areas: scanline, outline, hatch, crosshatch, or spiral
+    #[arg(long, default_value = "scanline")]
+    fill_mode: String,
+
+    /// Distance in pixels between hatch lines or spiral rings
+    #[arg(long, default_value = "6")]
+    fill_spacing: u32,
 
     /// Output file for testing
     #[arg(long)]
     output_file: Option<String>,
 
-    /// Output file for model parameters
+    /// Save bitmap filename
     #[arg(long)]
-    model_output_file: Option<String>,
+    save_bitmap: Option<String>,
+}
 
-    /// Save screenshot filename
+#[derive(clap::Args, Serialize)]
+struct RunArgs {
+    #[command(flatten)]
+    #[serde(flatten)]
+    model: ModelArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    output: OutputArgs,
+
+    /// Do not actually submit to the model, for testing
+    #[arg(short, long)]
+    no_submit: bool,
+
+    /// Input PNG file for testing
     #[arg(long)]
-    save_screenshot: Option<String>,
+    input_png: Option<String>,
 
-    /// Save bitmap filename
+    /// Save screenshot filename
     #[arg(long)]
-    save_bitmap: Option<String>,
+    save_screenshot: Option<String>,
 
     /// Disable looping
     #[arg(long)]
@@ -105,47 +259,279 @@ pub struct Args {
     #[arg(long)]
     no_trigger: bool,
 
-    /// Apply segmentation
+    /// Keep a conversation history for the current page, so the model sees its own previous responses
     #[arg(long)]
-    apply_segmentation: bool,
+    conversation: bool,
 
-    /// Enable web search (for Anthropic models)
+    /// Sets which corner the touch trigger listens to (UR, UL, LR, LL, upper-right, upper-left, lower-right, lower-left)
+    #[arg(long, default_value = "UR")]
+    trigger_corner: String,
+
+    /// Also trigger once the pen has been still for this many seconds, if the page changed
     #[arg(long)]
-    web_search: bool,
+    idle_trigger: Option<u64>,
 
-    /// Enable model thinking (for Anthropic models)
+    /// How much of the page, in percent, has to change since the last response for the idle trigger to fire
+    #[arg(long, default_value = "0.05")]
+    idle_min_change: f64,
+
+    /// Save current configuration to ~/.ghostwriter.toml and exit (same as `config --save`)
+    #[arg(long, hide = true)]
+    save_config: bool,
+}
+
+#[derive(clap::Args, Serialize)]
+struct OnceArgs {
+    /// PNG file to respond to instead of taking a screenshot
+    input_png: Option<String>,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    model: ModelArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    output: OutputArgs,
+
+    /// Do not actually submit to the model, for testing
+    #[arg(short, long)]
+    no_submit: bool,
+
+    /// Save screenshot filename
     #[arg(long)]
-    thinking: bool,
+    save_screenshot: Option<String>,
+}
 
-    /// Set the thinking token budget (for Anthropic models)
-    #[arg(long, default_value = "5000")]
-    thinking_tokens: u32,
+#[derive(clap::Args, Serialize)]
+struct ConfigArgs {
+    #[command(flatten)]
+    #[serde(flatten)]
+    run: RunArgs,
 
-    /// Set the log level. Try 'debug' or 'trace'
-    #[arg(long, default_value = "info")]
-    log_level: String,
+    /// Save the configuration to ~/.ghostwriter.toml instead of printing it
+    #[arg(long)]
+    #[serde(skip)]
+    save: bool,
+}
 
-    /// Sets which corner the touch trigger listens to (UR, UL, LR, LL, upper-right, upper-left, lower-right, lower-left)
-    #[arg(long, default_value = "UR")]
-    trigger_corner: String,
+#[derive(clap::Args)]
+struct ScreenshotArgs {
+    /// Where to save the PNG
+    #[arg(default_value = "screenshot.png")]
+    output: String,
+}
+
+#[derive(clap::Args, Serialize)]
+struct DrawArgs {
+    /// SVG file to draw, or - to read it from stdin
+    svg: String,
 
-    /// Save current configuration to ~/.ghostwriter.toml and exit
+    /// Skip running draw_text or draw_svg, for testing
     #[arg(long)]
-    save_config: bool,
+    no_draw: bool,
+
+    /// How to draw SVGs: vector (pen strokes along the paths, outlining filled shapes) or raster (scan the rendered bitmap)
+    #[arg(long, default_value = "vector")]
+    svg_mode: String,
+
+    /// How raster mode fills dark areas: scanline, outline, hatch, crosshatch, or spiral
+    #[arg(long, default_value = "scanline")]
+    fill_mode: String,
+
+    /// Distance in pixels between hatch lines or spiral rings
+    #[arg(long, default_value = "6")]
+    fill_spacing: u32,
+
+    /// Save bitmap filename
+    #[arg(long)]
+    save_bitmap: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct TypeArgs {
+    /// Text file to type; reads stdin when omitted or -
+    file: Option<String>,
+
+    /// Don't type anything, for testing
+    #[arg(long)]
+    no_draw: bool,
+}
+
+#[derive(clap::Args)]
+struct EvalArgs {
+    /// The test matrix: scenarios, cases, and attempts
+    #[arg(default_value = "evaluations/matrix.toml")]
+    matrix: String,
+
+    /// Override the number of attempts per case
+    #[arg(long)]
+    attempts: Option<u32>,
+
+    /// Only run this scenario (can be repeated)
+    #[arg(long)]
+    scenario: Vec<String>,
+
+    /// Only run this case (can be repeated)
+    #[arg(long)]
+    case: Vec<String>,
+
+    /// Score each run against the scenario's rubric.md with this model (overrides [judge] in the matrix)
+    #[arg(long)]
+    judge: Option<String>,
+
+    /// Don't score the runs, even if the matrix has a [judge]
+    #[arg(long)]
+    no_judge: bool,
 }
 
 fn main() -> Result<()> {
     dotenv().ok();
 
-    let args = Args::parse();
+    let matches = Cli::command().get_matches();
+    // The options for the default `run` don't mix with a subcommand, which takes its own after its name
+    if let Some((subcommand, _)) = matches.subcommand() {
+        if let Some(id) = matches
+            .ids()
+            .find(|id| id.as_str() != "log_level" && matches.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+        {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "--{} can't come before the {} subcommand; options go after its name",
+                        id.as_str().replace('_', "-"),
+                        subcommand
+                    ),
+                )
+                .exit();
+        }
+    }
+    let cli = Cli::from_arg_matches(&matches)?;
 
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(args.log_level.as_str()))
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(cli.log_level.as_str()))
         .format_timestamp_millis()
         .init();
 
+    match cli.command.unwrap_or(Command::Run(cli.run)) {
+        Command::Run(args) => run(&args, &cli.log_level),
+        Command::Once(args) => once(&args, &cli.log_level),
+        Command::Eval(args) => eval(&args),
+        Command::Config(args) => show_config(&args, &cli.log_level),
+        Command::Screenshot(args) => screenshot(&args),
+        Command::Draw(args) => draw(&args, &cli.log_level),
+        Command::Type(args) => type_text(&args),
+    }
+}
+
+fn run(args: &RunArgs, log_level: &str) -> Result<()> {
+    let config = Config::load(args, log_level)?;
+
+    if args.save_config {
+        config.save()?;
+        println!("Configuration saved to {:?}", Config::config_path()?);
+        return Ok(());
+    }
+
     setup_uinput()?;
+    ghostwriter(&config)
+}
+
+fn once(args: &OnceArgs, log_level: &str) -> Result<()> {
+    let mut config = Config::load(args, log_level)?;
+    config.no_loop = true;
+    config.no_trigger = true;
 
-    ghostwriter(&args)
+    if !config.no_draw {
+        setup_uinput()?;
+    }
+    ghostwriter(&config)
+}
+
+fn show_config(args: &ConfigArgs, log_level: &str) -> Result<()> {
+    let config = Config::load(args, log_level)?;
+    if args.save {
+        config.save()?;
+        println!("Configuration saved to {:?}", Config::config_path()?);
+    } else {
+        print!("{}", toml::to_string_pretty(&config)?);
+    }
+    Ok(())
+}
+
+fn screenshot(args: &ScreenshotArgs) -> Result<()> {
+    let mut screenshot = Screenshot::new()?;
+    screenshot.take_screenshot()?;
+    screenshot.save_image(&args.output)?;
+    println!("Screenshot saved to {}", args.output);
+    Ok(())
+}
+
+fn draw(args: &DrawArgs, log_level: &str) -> Result<()> {
+    let config = Config::load(args, log_level)?;
+    let svg_data = read_input(Some(&args.svg))?;
+
+    // Nothing gets typed, so no virtual keyboard
+    let mut keyboard = Keyboard::new(true, true);
+    let mut pen = Pen::new(config.no_draw);
+    draw_svg(&svg_data, &mut keyboard, &mut pen, &config)
+}
+
+fn type_text(args: &TypeArgs) -> Result<()> {
+    let text = read_input(args.file.as_deref())?;
+
+    if !args.no_draw {
+        setup_uinput()?;
+    }
+    let mut keyboard = Keyboard::new(args.no_draw, true);
+    if !args.no_draw {
+        // Give the tablet a moment to notice the new keyboard
+        sleep(Duration::from_secs(1));
+    }
+    draw_text(&text, &mut keyboard)
+}
+
+/// Read a whole file, or stdin for "-" or no file
+fn read_input(file: Option<&str>) -> Result<String> {
+    match file {
+        None | Some("-") => {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            Ok(input)
+        }
+        Some(file) => std::fs::read_to_string(file).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", file, e)),
+    }
+}
+
+fn eval(args: &EvalArgs) -> Result<()> {
+    let mut matrix = EvalMatrix::load(&args.matrix)?;
+    if let Some(attempts) = args.attempts {
+        matrix.attempts = attempts;
+    }
+    if !args.scenario.is_empty() {
+        matrix.scenarios = args.scenario.clone();
+    }
+    if !args.case.is_empty() {
+        matrix.cases.retain(|case| args.case.contains(&case.name));
+        if matrix.cases.is_empty() {
+            return Err(anyhow::anyhow!("No cases in {} match {:?}", args.matrix, args.case));
+        }
+    }
+
+    if let Some(model) = &args.judge {
+        matrix.judge = Some(FallbackModel {
+            engine: None,
+            model: model.clone(),
+            base_url: None,
+            api_key: None,
+        });
+    }
+    if args.no_judge {
+        matrix.judge = None;
+    }
+
+    let report = run_eval(&matrix)?;
+    println!("Report written to {}", report.display());
+    Ok(())
 }
 
 macro_rules! shared {
@@ -170,89 +556,293 @@ fn draw_text(text: &str, keyboard: &mut Keyboard) -> Result<()> {
     Ok(())
 }
 
-fn draw_svg(svg_data: &str, keyboard: &mut Keyboard, pen: &mut Pen, save_bitmap: Option<&String>, no_draw: bool) -> Result<()> {
+fn draw_svg(svg_data: &str, keyboard: &mut Keyboard, pen: &mut Pen, config: &Config) -> Result<()> {
     info!("Drawing SVG to the screen.");
     keyboard.progress_end()?;
     let bitmap = svg_to_bitmap(svg_data, VIRTUAL_WIDTH, VIRTUAL_HEIGHT)?;
-    if let Some(save_bitmap) = save_bitmap {
+    if let Some(save_bitmap) = &config.save_bitmap {
         write_bitmap_to_file(&bitmap, save_bitmap)?;
     }
-    if !no_draw {
-        pen.draw_bitmap(&bitmap)?;
+    if !config.no_draw {
+        if config.svg_mode == "raster" {
+            match FillMode::from_string(&config.fill_mode)? {
+                FillMode::Scanline => pen.draw_bitmap(&bitmap)?,
+                fill_mode => pen.draw_polylines(&fill_strokes(&bitmap, fill_mode, config.fill_spacing))?,
+            }
+        } else {
+            let polylines = svg_to_polylines(svg_data, VIRTUAL_WIDTH, VIRTUAL_HEIGHT)?;
+            pen.draw_polylines(&polylines)?;
+        }
     }
     Ok(())
 }
 
-fn determine_engine_name(engine_arg: &Option<String>, model: &str) -> Result<String> {
-    if let Some(engine) = engine_arg {
-        return Ok(engine.clone());
+/// Something a response put on the page, kept so the undo gesture can take it off again
+enum Drawn {
+    Text(String),
+    Svg(String),
+}
+
+type SharedDrawn = Arc<Mutex<Vec<Drawn>>>;
+
+/// Erase everything the last response drew, newest first
+fn undo(drawn: &SharedDrawn, keyboard: &Arc<Mutex<Keyboard>>, pen: &Arc<Mutex<Pen>>) -> Result<()> {
+    let drawn = std::mem::take(&mut *lock!(drawn));
+    if drawn.is_empty() {
+        info!("Nothing to undo");
+        return Ok(());
     }
 
-    if model.starts_with("gpt") {
-        Ok("openai".to_string())
-    } else if model.starts_with("claude") {
-        Ok("anthropic".to_string())
-    } else if model.starts_with("gemini") {
-        Ok("google".to_string())
-    } else {
-        Err(anyhow::anyhow!(
-            "Unable to guess engine from model name '{}'. Please specify --engine (openai, anthropic, or google)",
-            model
-        ))
+    info!("Undoing the last response");
+    lock!(keyboard).progress_end()?;
+    for item in drawn.iter().rev() {
+        match item {
+            Drawn::Text(text) => lock!(keyboard).erase(text)?,
+            Drawn::Svg(svg_data) => lock!(pen).erase_bitmap(&svg_to_bitmap(svg_data, VIRTUAL_WIDTH, VIRTUAL_HEIGHT)?)?,
+        }
     }
+    Ok(())
+}
+
+fn draw_text_callback(config: &Config, keyboard: &Arc<Mutex<Keyboard>>, drawn: &SharedDrawn) -> ToolCallback {
+    let output_file = config.output_file.clone();
+    let no_draw = config.no_draw;
+    let keyboard_clone = Arc::clone(keyboard);
+    let drawn = Arc::clone(drawn);
+
+    Box::new(move |arguments: json| {
+        let text = match arguments["text"].as_str() {
+            Some(t) => t,
+            None => {
+                log::error!("draw_text tool called without valid 'text' argument");
+                return ToolOutput::Error("Missing required 'text' argument".to_string());
+            }
+        };
+        if let Some(output_file) = &output_file {
+            if let Err(e) = std::fs::write(output_file, text) {
+                log::error!("Failed to write output file: {}", e);
+            }
+        }
+        if !no_draw {
+            if let Err(e) = draw_text(text, &mut lock!(keyboard_clone)) {
+                log::error!("Failed to draw text: {}", e);
+                return ToolOutput::Error(format!("Failed to draw text: {}", e));
+            }
+            lock!(drawn).push(Drawn::Text(text.to_string()));
+        }
+        ToolOutput::Text("Text written to the screen.".to_string())
+    })
+}
+
+fn draw_svg_callback(config: &Config, keyboard: &Arc<Mutex<Keyboard>>, pen: &Arc<Mutex<Pen>>, drawn: &SharedDrawn) -> ToolCallback {
+    let output_file = config.output_file.clone();
+    let config = config.clone();
+    let keyboard_clone = Arc::clone(keyboard);
+    let pen_clone = Arc::clone(pen);
+    let drawn = Arc::clone(drawn);
+
+    Box::new(move |arguments: json| {
+        let svg_data = match arguments["svg"].as_str() {
+            Some(svg) => svg,
+            None => {
+                log::error!("draw_svg tool called without valid 'svg' argument");
+                return ToolOutput::Error("Missing required 'svg' argument".to_string());
+            }
+        };
+        if let Some(output_file) = &output_file {
+            if let Err(e) = std::fs::write(output_file, svg_data) {
+                log::error!("Failed to write output file: {}", e);
+            }
+        }
+        let mut keyboard = lock!(keyboard_clone);
+        let mut pen = lock!(pen_clone);
+        if let Err(e) = draw_svg(svg_data, &mut keyboard, &mut pen, &config) {
+            log::error!("Failed to draw SVG: {}", e);
+            return ToolOutput::Error(format!("Failed to draw SVG: {}", e));
+        }
+        if !config.no_draw {
+            lock!(drawn).push(Drawn::Svg(svg_data.to_string()));
+        }
+        ToolOutput::Text("SVG drawn on the screen.".to_string())
+    })
+}
+
+/// A tool callback shared by every engine in the fallback chain
+#[derive(Clone)]
+struct SharedTool {
+    name: String,
+    definition: json,
+    callback: Rc<RefCell<ToolCallback>>,
+}
+
+/// A tool from one of the `[[mcp_servers]]`, which are started once and shared by every zone
+struct McpTool {
+    server: String,
+    tool: SharedTool,
 }
 
-fn create_engine(engine_name: &str, engine_options: &OptionMap) -> Result<Box<dyn LLMEngine>> {
-    match engine_name {
-        "openai" => Ok(Box::new(OpenAI::new(engine_options))),
-        "anthropic" => Ok(Box::new(Anthropic::new(engine_options))),
-        "google" => Ok(Box::new(Google::new(engine_options))),
-        _ => Err(anyhow::anyhow!(
-            "Unknown engine '{}'. Supported engines: openai, anthropic, google",
-            engine_name
-        )),
+/// Start every configured MCP server and collect its tools
+fn start_mcp_servers(config: &Config) -> Result<Vec<McpTool>> {
+    let mut tools = Vec::new();
+    for server in &config.mcp_servers {
+        for (tool_name, definition, callback) in load_mcp_tools(server)? {
+            info!("Loaded MCP tool {} from {}", tool_name, server.name);
+            tools.push(McpTool {
+                server: server.name.clone(),
+                tool: SharedTool {
+                    name: tool_name,
+                    definition,
+                    callback: Rc::new(RefCell::new(callback)),
+                },
+            });
+        }
     }
+    Ok(tools)
 }
 
-fn ghostwriter(args: &Args) -> Result<()> {
-    let config = Config::load(args)?;
+/// Where the drawing tools draw, and the record of what they drew for undo
+struct Outputs<'a> {
+    keyboard: &'a Arc<Mutex<Keyboard>>,
+    pen: &'a Arc<Mutex<Pen>>,
+    drawn: &'a SharedDrawn,
+}
 
-    // Handle --save-config option
-    if args.save_config {
-        config.save()?;
-        println!("Configuration saved to {:?}", Config::config_path()?);
-        return Ok(());
+/// Load every tool listed in the prompt's "tools" array. A name can be an MCP
+/// tool, or an MCP server for all of its tools. Any other name maps to a
+/// `tool_NAME.json` file, which either points at a built-in handler through
+/// `internal_command` or at a script through `external_command`.
+fn load_tools(prompt_json: &json, config: &Config, mcp_tools: &[McpTool], outputs: &Outputs) -> Result<Vec<SharedTool>> {
+    let tool_names = prompt_json["tools"]
+        .as_array()
+        .ok_or_else(|| anyhow::anyhow!("Prompt file '{}' missing required 'tools' list", config.prompt))?;
+
+    let mut tools = Vec::new();
+    for tool_name in tool_names {
+        let tool_name = tool_name
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Prompt file '{}' has a non-string entry in 'tools': {}", config.prompt, tool_name))?;
+
+        let from_mcp = mcp_tools
+            .iter()
+            .filter(|mcp| mcp.tool.name == tool_name || mcp.server == tool_name)
+            .collect::<Vec<_>>();
+        if !from_mcp.is_empty() {
+            for mcp in from_mcp {
+                if !tools.iter().any(|tool: &SharedTool| tool.name == mcp.tool.name) {
+                    debug!("Registering MCP tool {} from {}", mcp.tool.name, mcp.server);
+                    tools.push(mcp.tool.clone());
+                }
+            }
+            continue;
+        }
+
+        let definition =
+            load_tool_definition(tool_name).map_err(|e| anyhow::anyhow!("Prompt file '{}' references tool '{}': {}", config.prompt, tool_name, e))?;
+
+        if let Some(internal_command) = definition["internal_command"].as_str() {
+            let callback = match internal_command {
+                "draw_text" if config.no_keyboard => {
+                    info!("Skipping tool {} due to --no-keyboard", tool_name);
+                    continue;
+                }
+                "draw_svg" if config.no_svg => {
+                    info!("Skipping tool {} due to --no-svg", tool_name);
+                    continue;
+                }
+                "draw_text" => draw_text_callback(config, outputs.keyboard, outputs.drawn),
+                "draw_svg" => draw_svg_callback(config, outputs.keyboard, outputs.pen, outputs.drawn),
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "Tool '{}' uses unknown internal_command '{}'. Supported: draw_text, draw_svg",
+                        tool_name,
+                        internal_command
+                    ))
+                }
+            };
+            debug!("Registering built-in tool {} ({})", tool_name, internal_command);
+            tools.push(SharedTool {
+                name: tool_name.to_string(),
+                definition,
+                callback: Rc::new(RefCell::new(callback)),
+            });
+        } else if let Some(command) = definition["external_command"].as_str() {
+            info!("Registering external tool {} ({})", tool_name, command);
+            let callback = external_command_callback(command);
+            tools.push(SharedTool {
+                name: tool_name.to_string(),
+                definition,
+                callback: Rc::new(RefCell::new(callback)),
+            });
+        } else {
+            return Err(anyhow::anyhow!("Tool '{}' needs either an internal_command or an external_command", tool_name));
+        }
     }
 
-    let trigger_corner = TriggerCorner::from_string(&config.trigger_corner)?;
-    let keyboard = shared!(Keyboard::new(config.no_draw || config.no_keyboard, config.no_draw_progress,));
-    let pen = shared!(Pen::new(config.no_draw));
-    let touch = shared!(Touch::new(config.no_draw, trigger_corner));
+    if tools.is_empty() {
+        return Err(anyhow::anyhow!("No tools left to register for prompt '{}'", config.prompt));
+    }
 
-    // Give time for the virtual keyboard to be plugged in
-    sleep(Duration::from_millis(1000));
+    Ok(tools)
+}
 
-    lock!(touch).tap_middle_bottom()?;
-    sleep(Duration::from_millis(1000));
+/// Everything that went back and forth with the models during one trigger, for --model-output-file
+#[derive(Default)]
+struct Recording {
+    exchanges: Vec<json>,
+    tool_calls: Vec<json>,
+}
 
-    lock!(keyboard).progress("Keyboard loaded...")?;
+type SharedRecording = Rc<RefCell<Recording>>;
 
+fn register_tools(engine: &mut dyn LLMEngine, tools: &[SharedTool], recording: &SharedRecording) {
+    for tool in tools {
+        let name = tool.name.clone();
+        let callback = Rc::clone(&tool.callback);
+        let recording = Rc::clone(recording);
+        engine.register_tool(
+            &tool.name,
+            tool.definition.clone(),
+            Box::new(move |arguments: json| {
+                let started = Instant::now();
+                let output = (callback.borrow_mut())(arguments.clone());
+                let result = match &output {
+                    ToolOutput::Text(text) => json!({ "text": text }),
+                    ToolOutput::Image(data) => json!({ "image": format!("<image: {} bytes base64>", data.len()) }),
+                    ToolOutput::Error(error) => json!({ "error": error }),
+                };
+                recording.borrow_mut().tool_calls.push(json!({
+                    "name": name,
+                    "arguments": arguments,
+                    "result": result,
+                    "duration_ms": started.elapsed().as_millis(),
+                }));
+                output
+            }),
+        );
+    }
+}
+
+/// Options shared by every engine, from the config
+fn engine_options(target: &FallbackModel, config: &Config) -> OptionMap {
     let mut engine_options = OptionMap::new();
+    engine_options.insert("model".to_string(), target.model.clone());
 
-    let model = config.model.clone();
-    engine_options.insert("model".to_string(), model.clone());
-    debug!("Model: {}", model);
+    if let Some(base_url) = &target.base_url {
+        debug!("Engine base URL: {}", base_url);
+        engine_options.insert("base_url".to_string(), base_url.clone());
+    }
+    if let Some(api_key) = &target.api_key {
+        debug!("Using configured API key");
+        engine_options.insert("api_key".to_string(), api_key.clone());
+    }
 
-    let engine_name = determine_engine_name(&config.engine, &model)?;
-    debug!("Engine: {}", engine_name);
+    engine_options.insert("max_steps".to_string(), config.max_tool_steps.to_string());
+    engine_options.insert("max_history_images".to_string(), config.max_history_images.to_string());
+    engine_options.insert("max_retries".to_string(), config.max_retries.to_string());
 
-    if config.engine_base_url.is_some() {
-        debug!("Engine base URL: {}", config.engine_base_url.clone().unwrap());
-        engine_options.insert("base_url".to_string(), config.engine_base_url.clone().unwrap());
-    }
-    if config.engine_api_key.is_some() {
-        debug!("Using API key from CLI args");
-        engine_options.insert("api_key".to_string(), config.engine_api_key.clone().unwrap());
+    if config.stream {
+        debug!("Streaming responses");
+        engine_options.insert("stream".to_string(), "true".to_string());
     }
 
     if config.web_search {
@@ -266,70 +856,201 @@ fn ghostwriter(args: &Args) -> Result<()> {
         engine_options.insert("thinking_tokens".to_string(), config.thinking_tokens.to_string());
     }
 
-    let mut engine = create_engine(&engine_name, &engine_options)?;
+    if let Some(reasoning_effort) = &config.reasoning_effort {
+        debug!("Reasoning effort: {}", reasoning_effort);
+        engine_options.insert("reasoning_effort".to_string(), reasoning_effort.clone());
+    }
 
-    let output_file = config.output_file.clone();
-    let no_draw = config.no_draw;
-    let keyboard_clone = Arc::clone(&keyboard);
-
-    let tool_config_draw_text = load_config("tool_draw_text.json");
-
-    engine.register_tool(
-        "draw_text",
-        serde_json::from_str::<serde_json::Value>(tool_config_draw_text.as_str())?,
-        Box::new(move |arguments: json| {
-            let text = match arguments["text"].as_str() {
-                Some(t) => t,
-                None => {
-                    log::error!("draw_text tool called without valid 'text' argument");
-                    return;
-                }
-            };
-            if let Some(output_file) = &output_file {
-                if let Err(e) = std::fs::write(output_file, text) {
-                    log::error!("Failed to write output file: {}", e);
-                }
-            }
-            if !no_draw {
-                // let mut keyboard = lock!(keyboard_clone);
-                if let Err(e) = draw_text(text, &mut lock!(keyboard_clone)) {
-                    log::error!("Failed to draw text: {}", e);
-                }
+    if let Some(builtin_tools) = &config.builtin_tools {
+        debug!("Built-in tools: {}", builtin_tools);
+        engine_options.insert("builtin_tools".to_string(), builtin_tools.clone());
+    }
+
+    engine_options.insert("stateful".to_string(), (!config.no_stateful).to_string());
+    engine_options.insert("tool_mode".to_string(), config.tool_mode.clone());
+
+    if let Some(cassette) = &config.cassette {
+        debug!("Cassette: {} ({})", cassette, config.cassette_mode);
+        engine_options.insert("cassette".to_string(), cassette.clone());
+        engine_options.insert("cassette_mode".to_string(), config.cassette_mode.clone());
+        // Replaying never reaches the provider, so don't insist on a key
+        if config.cassette_mode == "replay" {
+            engine_options.entry("api_key".to_string()).or_insert_with(|| "replay".to_string());
+        }
+    }
+
+    engine_options
+}
+
+fn setup_engine(
+    engine_name: &str,
+    target: &FallbackModel,
+    config: &Config,
+    tools: &[SharedTool],
+    cancel: &Arc<AtomicBool>,
+    keyboard: &Arc<Mutex<Keyboard>>,
+    recording: &SharedRecording,
+) -> Result<Box<dyn LLMEngine>> {
+    let mut engine = create_engine(engine_name, &engine_options(target, config))?;
+    engine.set_cancel_flag(cancel.clone());
+
+    // Show a dot on screen every so often while the response streams in
+    let progress_keyboard = keyboard.clone();
+    let mut received_chars = 0;
+    engine.set_progress_callback(Box::new(move |text: &str| {
+        let before = received_chars / 250;
+        received_chars += text.len();
+        if received_chars / 250 > before {
+            lock!(progress_keyboard).progress(".").ok();
+        }
+    }));
+
+    let exchange_recording = Rc::clone(recording);
+    let engine_label = engine_name.to_string();
+    let model = target.model.clone();
+    let include_images = config.model_output_include_images;
+    engine.set_exchange_callback(Box::new(move |exchange: Exchange| {
+        let mut request = exchange.request;
+        if !include_images {
+            strip_images(&mut request);
+        }
+        exchange_recording.borrow_mut().exchanges.push(json!({
+            "engine": engine_label,
+            "model": model,
+            "request": request,
+            "response": exchange.response,
+            "duration_ms": exchange.duration_ms,
+        }));
+    }));
+
+    register_tools(engine.as_mut(), tools, recording);
+    Ok(engine)
+}
+
+/// Wait for a finger gesture, or a pen gesture when those are in use
+fn wait_for_trigger(touch: &Arc<Mutex<Touch>>, pen_trigger: &mut Option<PenTrigger>) -> Result<Trigger> {
+    let Some(pen_trigger) = pen_trigger else {
+        return lock!(touch).wait_for_gesture();
+    };
+    loop {
+        if let Some(trigger) = lock!(touch).poll_gesture()? {
+            return Ok(trigger);
+        }
+        if let Some(trigger) = pen_trigger.poll()? {
+            return Ok(trigger);
+        }
+        sleep(Duration::from_millis(20));
+    }
+}
+
+/// What answers a trigger zone: its settings, and an engine for its model and each fallback
+struct Assistant {
+    name: String,
+    config: Config,
+    engines: Vec<(String, String, Box<dyn LLMEngine>)>,
+}
+
+/// Set up the assistant for one of the `[[zones]]`, or for the main trigger corner when `zone` is None.
+/// A zone with its own list of tools only gets the MCP tools on that list.
+fn setup_assistant(
+    config: &Config,
+    zone: Option<&TriggerZone>,
+    mcp_tools: &[McpTool],
+    outputs: &Outputs,
+    cancel: &Arc<AtomicBool>,
+    recording: &SharedRecording,
+) -> Result<Assistant> {
+    let name = zone.map_or("main", |zone| zone.name.as_str());
+    let config = zone.map_or_else(|| config.clone(), |zone| zone.apply(config));
+    let mut prompt_json = serde_json::from_str::<json>(load_config(&config.prompt).as_str())?;
+    match zone.and_then(|zone| zone.tools.as_ref()) {
+        Some(tool_names) => prompt_json["tools"] = json!(tool_names),
+        // Without a zone's own list, every MCP tool comes along with the prompt's tools
+        None => {
+            if let Some(tool_names) = prompt_json["tools"].as_array_mut() {
+                tool_names.extend(mcp_tools.iter().map(|mcp| json!(mcp.tool.name)));
             }
-        }),
-    );
+        }
+    }
+    let tools = load_tools(&prompt_json, &config, mcp_tools, outputs)?;
+
+    // The configured model first, then the fallbacks in order
+    let mut targets = vec![FallbackModel {
+        engine: config.engine.clone(),
+        model: config.model.clone(),
+        base_url: config.engine_base_url.clone(),
+        api_key: config.engine_api_key.clone(),
+    }];
+    targets.extend(config.fallback_models.iter().cloned());
+
+    let mut engines = Vec::new();
+    for target in &targets {
+        let engine_name = determine_engine_name(&target.engine, &target.model)?;
+        debug!("Zone: {} Engine: {} Model: {}", name, engine_name, target.model);
+        let engine = setup_engine(&engine_name, target, &config, &tools, cancel, outputs.keyboard, recording)?;
+        engines.push((engine_name, target.model.clone(), engine));
+    }
 
-    let output_file = config.output_file.clone();
-    let save_bitmap = config.save_bitmap.clone();
-    let no_draw = config.no_draw;
-    let keyboard_clone = Arc::clone(&keyboard);
-    let pen_clone = Arc::clone(&pen);
+    Ok(Assistant {
+        name: name.to_string(),
+        config,
+        engines,
+    })
+}
 
-    if !config.no_svg {
-        let tool_config_draw_svg = load_config("tool_draw_svg.json");
-        engine.register_tool(
-            "draw_svg",
-            serde_json::from_str::<serde_json::Value>(tool_config_draw_svg.as_str())?,
-            Box::new(move |arguments: json| {
-                let svg_data = match arguments["svg"].as_str() {
-                    Some(svg) => svg,
-                    None => {
-                        log::error!("draw_svg tool called without valid 'svg' argument");
-                        return;
-                    }
-                };
-                if let Some(output_file) = &output_file {
-                    if let Err(e) = std::fs::write(output_file, svg_data) {
-                        log::error!("Failed to write output file: {}", e);
-                    }
-                }
-                let mut keyboard = lock!(keyboard_clone);
-                let mut pen = lock!(pen_clone);
-                if let Err(e) = draw_svg(svg_data, &mut keyboard, &mut pen, save_bitmap.as_ref(), no_draw) {
-                    log::error!("Failed to draw SVG: {}", e);
-                }
-            }),
-        );
+fn ghostwriter(config: &Config) -> Result<()> {
+    let trigger_corner = TriggerCorner::from_string(&config.trigger_corner)?;
+    let mut gesture_actions = parse_gesture_actions(&config.gestures)?;
+    if config.idle_trigger.is_some() && !config.gestures.contains_key("idle") {
+        gesture_actions.insert(Gesture::Idle, GestureAction::Prompt);
+    }
+    // The main trigger corner is zone 0, then the [[zones]] in order
+    let mut areas = vec![TriggerArea::Corner(trigger_corner)];
+    for zone in &config.zones {
+        areas.push(zone.area()?);
+    }
+    let idle = config
+        .idle_trigger
+        .filter(|_| gesture_actions.contains_key(&Gesture::Idle))
+        .map(Duration::from_secs);
+    let mut pen_trigger = [Gesture::PenHover, Gesture::PenCircle, Gesture::Idle]
+        .iter()
+        .any(|gesture| gesture_actions.contains_key(gesture))
+        .then(|| {
+            PenTrigger::new(
+                config.no_draw,
+                areas.clone(),
+                gesture_actions.contains_key(&Gesture::PenHover),
+                gesture_actions.contains_key(&Gesture::PenCircle),
+                idle,
+            )
+        });
+    let recognizer = GestureRecognizer::new(areas, gesture_actions.contains_key(&Gesture::CornerDoubleTap));
+    let keyboard = shared!(Keyboard::new(config.no_draw || config.no_keyboard, config.no_draw_progress,));
+    let pen = shared!(Pen::new(config.no_draw));
+    let touch = shared!(Touch::new(config.no_draw, recognizer));
+
+    // Give time for the virtual keyboard to be plugged in
+    sleep(Duration::from_millis(1000));
+
+    lock!(touch).tap_middle_bottom()?;
+    sleep(Duration::from_millis(1000));
+
+    lock!(keyboard).progress("Keyboard loaded...")?;
+
+    let drawn = SharedDrawn::default();
+    let cancel = Arc::new(AtomicBool::new(false));
+    let recording = SharedRecording::default();
+
+    let mcp_tools = start_mcp_servers(config)?;
+    let outputs = Outputs {
+        keyboard: &keyboard,
+        pen: &pen,
+        drawn: &drawn,
+    };
+    let mut assistants = vec![setup_assistant(config, None, &mcp_tools, &outputs, &cancel, &recording)?];
+    for zone in &config.zones {
+        assistants.push(setup_assistant(config, Some(zone), &mcp_tools, &outputs, &cancel, &recording)?);
     }
 
     lock!(keyboard).progress("Tools initialized.")?;
@@ -337,40 +1058,102 @@ fn ghostwriter(args: &Args) -> Result<()> {
     lock!(keyboard).progress_end()?;
     sleep(Duration::from_millis(1000));
 
+    let ledger = Ledger::new(match &config.usage_ledger {
+        Some(path) => path.into(),
+        None => Ledger::default_path()?,
+    });
+
+    let mut previous_screenshot: Option<Vec<u8>> = None;
+    // The page as the last response left it, to tell whether the idle trigger has anything new to respond to
+    let mut idle_baseline = if idle.is_some() { Some(screenshot_png()?) } else { None };
+    // Recent pages as the last response on each left them, newest first, to find the new ink for --crop-new-ink
+    let mut pages: Vec<Vec<u8>> = Vec::new();
+
+    // A prompting gesture that cancelled the last request, to run next
+    let mut pending_trigger: Option<Trigger> = None;
+
     loop {
-        if config.no_trigger {
+        let (action, zone, gesture) = if config.no_trigger {
             debug!("Skipping waiting for trigger");
+            (GestureAction::Prompt, 0, None)
+        } else if let Some(Trigger { gesture, zone }) = pending_trigger.take() {
+            let action = gesture_actions[&gesture];
+            info!("Running {:?} for the {} in the {} zone", action, gesture.name(), assistants[zone].name);
+            (action, zone, Some(gesture))
         } else {
             info!(
-                "Waiting for trigger (hand-touch in the {} corner)...",
-                match TriggerCorner::from_string(&config.trigger_corner).unwrap() {
+                "Waiting for a gesture (corner gestures in the {} corner; {})...",
+                match trigger_corner {
                     TriggerCorner::UpperRight => "upper-right",
                     TriggerCorner::UpperLeft => "upper-left",
                     TriggerCorner::LowerRight => "lower-right",
                     TriggerCorner::LowerLeft => "lower-left",
-                }
+                },
+                gesture_actions
+                    .iter()
+                    .map(|(gesture, action)| format!("{} to {:?}", gesture.name(), action))
+                    .chain(config.zones.iter().map(|zone| format!("zone {}", zone.name)))
+                    .collect::<Vec<_>>()
+                    .join(", ")
             );
-            lock!(touch).wait_for_trigger()?;
+            if let Some(pen_trigger) = &mut pen_trigger {
+                pen_trigger.flush()?;
+            }
+            loop {
+                let Trigger { gesture, zone } = wait_for_trigger(&touch, &mut pen_trigger)?;
+                match gesture_actions.get(&gesture) {
+                    Some(action) => {
+                        info!("Got {} in the {} zone, running {:?}", gesture.name(), assistants[zone].name, action);
+                        break (*action, zone, Some(gesture));
+                    }
+                    None => debug!("Ignoring {}, it has no action", gesture.name()),
+                }
+            }
+        };
+        let Assistant {
+            config: zone_config, engines, ..
+        } = &mut assistants[zone];
+
+        match action {
+            GestureAction::Undo => {
+                undo(&drawn, &keyboard, &pen)?;
+                continue;
+            }
+            GestureAction::Cancel | GestureAction::None => {
+                debug!("Nothing running to cancel");
+                continue;
+            }
+            GestureAction::Prompt | GestureAction::NewPrompt | GestureAction::Continue => {}
         }
 
+        let trigger_started = Instant::now();
+        // The idle trigger only counts once enough of the page changed, so look before tapping anything
+        let idle_screenshot = match (gesture, &idle_baseline) {
+            (Some(Gesture::Idle), Some(idle_baseline)) => {
+                let screenshot_data = take_screenshot(config)?;
+                let changed = changed_percent(idle_baseline, &screenshot_data)?;
+                if changed < config.idle_min_change {
+                    info!("Only {:.3}% of the page changed since the last response, not responding", changed);
+                    continue;
+                }
+                debug!("{:.3}% of the page changed since the last response", changed);
+                Some(screenshot_data)
+            }
+            _ => None,
+        };
+
         // Sleep a bit to differentiate the touches
         sleep(Duration::from_millis(100));
         lock!(touch).tap_middle_bottom()?;
         // sleep(Duration::from_millis(1000));
         // lock!(keyboard).progress("Taking screenshot...")?;
 
-        info!("Getting screenshot (or loading input image)");
-        let base64_image = if let Some(input_png) = &config.input_png {
-            BASE64_STANDARD.encode(std::fs::read(input_png)?)
-        } else {
-            let mut screenshot = Screenshot::new()?;
-            screenshot.take_screenshot()?;
-            if let Some(save_screenshot) = &config.save_screenshot {
-                info!("Saving screenshot to {}", save_screenshot);
-                screenshot.save_image(save_screenshot)?;
-            }
-            screenshot.base64()?
+        let screenshot_data = match idle_screenshot {
+            Some(screenshot_data) => screenshot_data,
+            None => take_screenshot(config)?,
         };
+        let base64_image = BASE64_STANDARD.encode(&screenshot_data);
+        let screenshot_ms = trigger_started.elapsed().as_millis();
 
         if config.no_submit {
             info!("Image not submitted to model due to --no-submit flag");
@@ -378,11 +1161,27 @@ fn ghostwriter(args: &Args) -> Result<()> {
             return Ok(());
         }
 
-        let prompt_general_raw = load_config(&config.prompt);
+        if let Some(daily_budget) = config.daily_budget {
+            let spent = ledger.total_today()?;
+            if spent >= daily_budget {
+                info!("Daily budget of ${:.2} reached (${:.4} spent today), not submitting", daily_budget, spent);
+                lock!(keyboard).progress(" daily budget reached. ")?;
+                if config.no_loop {
+                    break Ok(());
+                }
+                // Without a trigger to wait for, the next check would come right away
+                if config.no_trigger {
+                    sleep(BUDGET_RECHECK_INTERVAL);
+                }
+                continue;
+            }
+        }
+
+        let prompt_general_raw = load_config(&zone_config.prompt);
         let prompt_general_json = serde_json::from_str::<serde_json::Value>(prompt_general_raw.as_str())?;
         let prompt = prompt_general_json["prompt"]
             .as_str()
-            .ok_or_else(|| anyhow::anyhow!("Prompt file '{}' missing required 'prompt' field", config.prompt))?;
+            .ok_or_else(|| anyhow::anyhow!("Prompt file '{}' missing required 'prompt' field", zone_config.prompt))?;
 
         let segmentation_description = if config.apply_segmentation {
             info!("Building image segmentation");
@@ -401,25 +1200,231 @@ fn ghostwriter(args: &Args) -> Result<()> {
         };
         debug!("Segmentation description: {}", segmentation_description);
 
-        engine.clear_content();
-        engine.add_image_content(&base64_image);
+        let new_conversation = match &previous_screenshot {
+            _ if action == GestureAction::NewPrompt => true,
+            _ if action == GestureAction::Continue => false,
+            _ if !config.conversation => true,
+            Some(previous_screenshot) if !is_same_page(previous_screenshot, &screenshot_data)? => {
+                info!("Page changed, starting a new conversation");
+                true
+            }
+            _ => false,
+        };
 
-        if config.apply_segmentation {
-            engine.add_text_content(
-               format!("Here are interesting regions based on an automatic segmentation algorithm. Use them to help identify the exact location of interesting features.\n\n{}", segmentation_description).as_str()
-            );
+        let new_ink = match pages.iter().find(|page| is_same_page(page, &screenshot_data).unwrap_or(false)) {
+            Some(page) if config.crop_new_ink => NewInk::find(page, &screenshot_data)?,
+            _ => None,
+        };
+        if let Some(new_ink) = &new_ink {
+            info!("Sending the new ink at {:?} and the page at half resolution", new_ink.bounds);
         }
+        previous_screenshot = Some(screenshot_data);
 
-        engine.add_text_content(prompt);
+        for (_, _, engine) in engines.iter_mut() {
+            engine.clear_content();
+            if new_conversation {
+                engine.clear_history();
+            }
+            match &new_ink {
+                Some(new_ink) => {
+                    engine.add_image_content(&BASE64_STANDARD.encode(&new_ink.context_png));
+                    engine.add_image_content(&BASE64_STANDARD.encode(&new_ink.crop_png));
+                    engine.add_text_content(&new_ink.description());
+                }
+                None => engine.add_image_content(&base64_image),
+            }
+
+            if config.apply_segmentation {
+                engine.add_text_content(
+                   format!("Here are interesting regions based on an automatic segmentation algorithm. Use them to help identify the exact location of interesting features.\n\n{}", segmentation_description).as_str()
+                );
+            }
+
+            engine.add_text_content(prompt);
+        }
 
-        info!("Executing the engine (call out to {}", engine_name);
         lock!(keyboard).progress("thinking...")?;
-        if engine.execute().is_err() {
-            lock!(keyboard).progress(" model error. ")?;
+
+        // Watch for a cancel gesture, or another trigger, while the model works
+        cancel.store(false, Ordering::SeqCst);
+        lock!(drawn).clear();
+        let done = Arc::new(AtomicBool::new(false));
+        let watcher = if config.no_trigger {
+            None
+        } else {
+            let touch = touch.clone();
+            let cancel = cancel.clone();
+            let done = done.clone();
+            let gesture_actions = gesture_actions.clone();
+            // Hands back a prompting gesture, so it starts the next request instead of being lost
+            Some(spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    let Some(trigger) = lock!(touch).poll_gesture().unwrap_or(None) else {
+                        sleep(Duration::from_millis(50));
+                        continue;
+                    };
+                    match gesture_actions.get(&trigger.gesture) {
+                        Some(GestureAction::Cancel) => {
+                            info!("Got {} while working, cancelling request", trigger.gesture.name());
+                            cancel.store(true, Ordering::SeqCst);
+                            return None;
+                        }
+                        Some(action) if action.prompts() => {
+                            info!("Got {} while working, cancelling request and starting over", trigger.gesture.name());
+                            cancel.store(true, Ordering::SeqCst);
+                            return Some(trigger);
+                        }
+                        _ => {}
+                    }
+                }
+                None
+            }))
+        };
+
+        *recording.borrow_mut() = Recording::default();
+        let model_started = Instant::now();
+        let mut result = Ok(Usage::default());
+        let mut answered_by = String::new();
+        let mut answered_index = None;
+        // The model and tokens used by each engine tried, answering or not
+        let mut attempts: Vec<(String, Usage)> = Vec::new();
+        let engine_count = engines.len();
+        for (index, (engine_name, model, engine)) in engines.iter_mut().enumerate() {
+            info!("Executing the engine (call out to {} with {})", engine_name, model);
+            let tool_calls_before = recording.borrow().tool_calls.len();
+            result = engine.execute();
+            attempts.push((model.clone(), engine.last_usage()));
+            let ran_tools = recording.borrow().tool_calls.len() > tool_calls_before;
+            match &result {
+                Ok(_) => {
+                    info!("Answered by {} ({})", engine_name, model);
+                    answered_by = model.clone();
+                    answered_index = Some(index);
+                    break;
+                }
+                // Another model would start over and draw on the page a second time
+                Err(e) if ran_tools => {
+                    warn!("{} ({}) failed after running tools, not falling back: {}", engine_name, model, e);
+                    break;
+                }
+                // Only move on when the provider is having trouble, not for mistakes we'd repeat everywhere
+                Err(e) if index + 1 < engine_count && !cancel.load(Ordering::SeqCst) && e.downcast_ref::<EngineError>().is_some_and(|e| e.is_retryable()) => {
+                    warn!("{} ({}) failed: {}. Falling back to the next model", engine_name, model, e);
+                }
+                Err(_) => break,
+            }
+        }
+        let model_ms = model_started.elapsed().as_millis();
+        done.store(true, Ordering::SeqCst);
+
+        // Every engine continues from this turn, whichever one answered it
+        if let Some(answered_index) = answered_index.filter(|_| engine_count > 1) {
+            let history = engines[answered_index].2.history().clone();
+            for (index, (_, _, engine)) in engines.iter_mut().enumerate() {
+                if index != answered_index {
+                    engine.set_history(&history);
+                }
+            }
+        }
+        if let Some(watcher) = watcher {
+            pending_trigger = watcher.join().ok().flatten();
+        }
+
+        // Every attempt that used tokens is paid for, whether it answered, failed, or was cancelled
+        let mut usage = Usage::default();
+        let mut cost = Some(0.0);
+        let mut unpriced = Vec::new();
+        for (model, attempt_usage) in attempts.iter().filter(|(model, usage)| *usage != Usage::default() || *model == answered_by) {
+            let attempt_cost = price_for(&config.prices, model).map(|price| price.cost(attempt_usage));
+            ledger.record(model, attempt_usage, attempt_cost)?;
+            usage += *attempt_usage;
+            cost = cost.zip(attempt_cost).map(|(total, attempt_cost)| total + attempt_cost);
+            if attempt_cost.is_none() {
+                unpriced.push(model.as_str());
+            }
+        }
+
+        if let Some(model_output_file) = &config.model_output_file {
+            let recording = recording.borrow();
+            let last_call = recording.tool_calls.last();
+            let model_output = json!({
+                // The last tool call, at the top level like before, so existing scripts keep working
+                "function": last_call.map(|call| call["name"].clone()),
+                "arguments": last_call.map(|call| call["arguments"].clone()),
+                "model": (!answered_by.is_empty()).then_some(&answered_by),
+                "tool_calls": recording.tool_calls,
+                "exchanges": recording.exchanges,
+                "usage": usage,
+                "cost": cost,
+                "error": result.as_ref().err().map(|e| e.to_string()),
+                "timings": {
+                    "screenshot_ms": screenshot_ms,
+                    "model_ms": model_ms,
+                    "total_ms": trigger_started.elapsed().as_millis(),
+                },
+            });
+            info!("Saving model exchange to {}", model_output_file);
+            std::fs::write(model_output_file, serde_json::to_string_pretty(&model_output)?)?;
+        }
+
+        info!(
+            "Used {} input and {} output tokens ({} thinking), costing {}; ${:.4} spent today",
+            usage.input_tokens,
+            usage.output_tokens,
+            usage.thinking_tokens,
+            cost.map_or(format!("an unknown amount (no price for {})", unpriced.join(", ")), |cost| format!(
+                "${:.4}",
+                cost
+            )),
+            ledger.total_today()?
+        );
+        if let Err(e) = result {
+            // Show what kind of failure it was, so it's clear whether to fix the setup or just try again
+            let category = if cancel.load(Ordering::SeqCst) {
+                "cancelled"
+            } else {
+                EngineError::category_of(&e)
+            };
+            info!("Model error ({}): {}", category, e);
+            lock!(keyboard).progress(&format!(" {}. ", category))?;
         }
 
         if config.no_loop {
             break Ok(());
         }
+
+        // Remember the page as this response left it
+        if idle.is_some() || (config.crop_new_ink && config.input_png.is_none()) {
+            let page = screenshot_png()?;
+            if config.crop_new_ink {
+                pages.retain(|previous| !is_same_page(previous, &page).unwrap_or(false));
+                pages.insert(0, page.clone());
+                pages.truncate(MAX_PAGES);
+            }
+            if idle.is_some() {
+                idle_baseline = Some(page);
+            }
+        }
+    }
+}
+
+fn screenshot_png() -> Result<Vec<u8>> {
+    let mut screenshot = Screenshot::new()?;
+    screenshot.take_screenshot()?;
+    Ok(screenshot.png_data().to_vec())
+}
+
+/// The page as it is now, or the --input-png standing in for it
+fn take_screenshot(config: &Config) -> Result<Vec<u8>> {
+    info!("Getting screenshot (or loading input image)");
+    if let Some(input_png) = &config.input_png {
+        return Ok(std::fs::read(input_png)?);
+    }
+    let mut screenshot = Screenshot::new()?;
+    screenshot.take_screenshot()?;
+    if let Some(save_screenshot) = &config.save_screenshot {
+        info!("Saving screenshot to {}", save_screenshot);
+        screenshot.save_image(save_screenshot)?;
     }
+    Ok(screenshot.png_data().to_vec())
 }
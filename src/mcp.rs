@@ -0,0 +1,295 @@
+use anyhow::Result;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_json::Value as json;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::Duration;
+
+use crate::llm_engine::{ToolCallback, ToolOutput};
+
+const PROTOCOL_VERSION: &str = "2025-06-18";
+
+// How long to wait for a server to answer a single request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// An MCP server to launch, from `[[mcp_servers]]` in ~/.ghostwriter.toml
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct McpServerConfig {
+    pub name: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Only register these tools from the server (default: all of them)
+    #[serde(default)]
+    pub tools: Option<Vec<String>>,
+    /// "loop" (the default) sends the results of this server's tools back to the
+    /// model for another step; "end" ends the turn once one of them has run
+    #[serde(default = "default_next_action")]
+    pub next_action: String,
+}
+
+fn default_next_action() -> String {
+    "loop".to_string()
+}
+
+/// A Model Context Protocol client talking JSON-RPC to a server over its stdin/stdout
+pub struct McpClient {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+    next_id: u64,
+}
+
+impl McpClient {
+    /// Launch the server and perform the initialization handshake
+    pub fn start(config: &McpServerConfig) -> Result<Self> {
+        info!("Starting MCP server {}: {} {}", config.name, config.command, config.args.join(" "));
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .envs(&config.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to start MCP server '{}' ({}): {}", config.name, config.command, e))?;
+
+        let stdin = child.stdin.take().ok_or_else(|| anyhow::anyhow!("MCP server '{}' has no stdin", config.name))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("MCP server '{}' has no stdout", config.name))?;
+
+        // Read on a separate thread so a stuck server can time out instead of hanging us
+        let (sender, lines) = channel();
+        spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut client = Self {
+            name: config.name.clone(),
+            child,
+            stdin,
+            lines,
+            next_id: 1,
+        };
+
+        let result = client.request(
+            "initialize",
+            json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": {},
+                "clientInfo": {
+                    "name": "ghostwriter",
+                    "version": env!("CARGO_PKG_VERSION"),
+                }
+            }),
+        )?;
+        debug!("MCP server {} initialized: {}", client.name, result);
+        client.notify("notifications/initialized", json!({}))?;
+
+        Ok(client)
+    }
+
+    /// All tools the server offers, following pagination
+    pub fn list_tools(&mut self) -> Result<Vec<json>> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = self.request("tools/list", params)?;
+            tools.extend(result["tools"].as_array().cloned().unwrap_or_default());
+            match result["nextCursor"].as_str() {
+                Some(next) => cursor = Some(next.to_string()),
+                None => break,
+            }
+        }
+        Ok(tools)
+    }
+
+    pub fn call_tool(&mut self, name: &str, arguments: json) -> ToolOutput {
+        let result = match self.request("tools/call", json!({ "name": name, "arguments": arguments })) {
+            Ok(result) => result,
+            Err(e) => return ToolOutput::Error(e.to_string()),
+        };
+
+        let mut text = Vec::new();
+        let mut image = None;
+        for item in result["content"].as_array().unwrap_or(&Vec::new()) {
+            match item["type"].as_str() {
+                Some("text") => text.push(item["text"].as_str().unwrap_or_default().to_string()),
+                Some("image") => image = item["data"].as_str().map(|data| data.to_string()),
+                Some("resource") => text.push(item["resource"]["text"].as_str().unwrap_or_default().to_string()),
+                _ => debug!("Ignoring MCP content: {}", item),
+            }
+        }
+        if text.is_empty() && !result["structuredContent"].is_null() {
+            text.push(result["structuredContent"].to_string());
+        }
+
+        if result["isError"].as_bool() == Some(true) {
+            ToolOutput::Error(text.join("\n"))
+        } else if let (Some(image), true) = (image, text.is_empty()) {
+            ToolOutput::Image(image)
+        } else {
+            ToolOutput::Text(text.join("\n"))
+        }
+    }
+
+    fn send(&mut self, message: &json) -> Result<()> {
+        debug!("MCP {} <- {}", self.name, message);
+        writeln!(self.stdin, "{}", message)?;
+        self.stdin.flush()?;
+        Ok(())
+    }
+
+    fn notify(&mut self, method: &str, params: json) -> Result<()> {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    fn request(&mut self, method: &str, params: json) -> Result<json> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))?;
+
+        loop {
+            let line = match self.lines.recv_timeout(REQUEST_TIMEOUT) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => return Err(anyhow::anyhow!("MCP server '{}' timed out on {}", self.name, method)),
+                Err(RecvTimeoutError::Disconnected) => return Err(anyhow::anyhow!("MCP server '{}' exited", self.name)),
+            };
+            debug!("MCP {} -> {}", self.name, line);
+
+            let Ok(message) = serde_json::from_str::<json>(&line) else {
+                warn!("MCP server '{}' sent a non-JSON line: {}", self.name, line);
+                continue;
+            };
+
+            // Requests from the server (like ping) need an answer, notifications don't
+            if let Some(server_method) = message["method"].as_str() {
+                if !message["id"].is_null() {
+                    let reply = if server_method == "ping" {
+                        json!({ "jsonrpc": "2.0", "id": message["id"], "result": {} })
+                    } else {
+                        json!({ "jsonrpc": "2.0", "id": message["id"], "error": { "code": -32601, "message": "Method not found" } })
+                    };
+                    self.send(&reply)?;
+                }
+                continue;
+            }
+
+            if message["id"].as_u64() != Some(id) {
+                continue;
+            }
+            if !message["error"].is_null() {
+                return Err(anyhow::anyhow!("MCP server '{}' error on {}: {}", self.name, method, message["error"]));
+            }
+            return Ok(message["result"].clone());
+        }
+    }
+}
+
+impl Drop for McpClient {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+/// Start a server and return its tools as (name, definition, callback), ready for `register_tool`.
+/// The tools take the server's `next_action`, so by default their results go back to the model.
+pub fn load_mcp_tools(config: &McpServerConfig) -> Result<Vec<(String, json, ToolCallback)>> {
+    let mut client = McpClient::start(config)?;
+    let tools = client.list_tools()?;
+    let client = Arc::new(Mutex::new(client));
+
+    let mut registrations = Vec::new();
+    for tool in tools {
+        let Some(name) = tool["name"].as_str().map(|name| name.to_string()) else {
+            continue;
+        };
+        if config.tools.as_ref().is_some_and(|allowed| !allowed.contains(&name)) {
+            debug!("Skipping MCP tool {} from {}, not in its tools list", name, config.name);
+            continue;
+        }
+
+        let definition = json!({
+            "name": name,
+            "description": tool["description"].as_str().unwrap_or_default(),
+            "parameters": if tool["inputSchema"].is_null() { json!({ "type": "object", "properties": {} }) } else { tool["inputSchema"].clone() },
+            "next_action": config.next_action,
+        });
+
+        let client = client.clone();
+        let tool_name = name.clone();
+        let callback: ToolCallback = Box::new(move |arguments: json| client.lock().unwrap().call_tool(&tool_name, arguments));
+        registrations.push((name, definition, callback));
+    }
+
+    Ok(registrations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture_server() -> McpServerConfig {
+        McpServerConfig {
+            name: "fixture".to_string(),
+            command: "sh".to_string(),
+            args: vec![concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/mcp_server.sh").to_string()],
+            env: HashMap::new(),
+            tools: None,
+            next_action: default_next_action(),
+        }
+    }
+
+    #[test]
+    fn lists_and_calls_tools() {
+        let mut client = McpClient::start(&fixture_server()).unwrap();
+
+        let tools = client.list_tools().unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0]["name"], "echo");
+
+        match client.call_tool("echo", json!({ "text": "hello" })) {
+            ToolOutput::Text(text) => assert_eq!(text, "echo: hello"),
+            output => panic!("Expected text, got {:?}", output),
+        }
+    }
+
+    #[test]
+    fn tools_take_the_server_next_action() {
+        let mut config = fixture_server();
+        let tools = load_mcp_tools(&config).unwrap();
+        assert_eq!(tools[0].1["next_action"], "loop");
+
+        config.next_action = "end".to_string();
+        let mut tools = load_mcp_tools(&config).unwrap();
+        assert_eq!(tools[0].1["next_action"], "end");
+        let (_, _, callback) = &mut tools[0];
+        assert!(matches!(callback(json!({ "text": "hi" })), ToolOutput::Text(text) if text == "echo: hi"));
+    }
+
+    #[test]
+    fn skips_tools_not_in_the_list() {
+        let mut config = fixture_server();
+        config.tools = Some(vec!["something_else".to_string()]);
+        assert!(load_mcp_tools(&config).unwrap().is_empty());
+    }
+}
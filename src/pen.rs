@@ -8,12 +8,14 @@ use std::time::Duration;
 use crate::device::DeviceModel;
 
 // Output dimensions remain the same for both devices
-const VIRTUAL_WIDTH: u32 = 768;
-const VIRTUAL_HEIGHT: u32 = 1024;
+pub(crate) const VIRTUAL_WIDTH: u32 = 768;
+pub(crate) const VIRTUAL_HEIGHT: u32 = 1024;
 
 pub struct Pen {
     device: Option<Device>,
     device_model: DeviceModel,
+    /// Draw with the eraser end of the pen
+    eraser: bool,
 }
 
 impl Pen {
@@ -21,19 +23,30 @@ impl Pen {
         let device_model = DeviceModel::detect();
         info!("Pen using device model: {}", device_model.name());
 
-        let pen_input_device = match device_model {
-            DeviceModel::Remarkable2 => "/dev/input/event1",
-            DeviceModel::RemarkablePaperPro => "/dev/input/event2",
-            DeviceModel::Unknown => "/dev/input/event1", // Default to RM2
+        let device = if no_draw {
+            None
+        } else {
+            Some(Device::open(Self::input_device(device_model)).unwrap())
         };
 
-        let device = if no_draw { None } else { Some(Device::open(pen_input_device).unwrap()) };
+        Self {
+            device,
+            device_model,
+            eraser: false,
+        }
+    }
 
-        Self { device, device_model }
+    /// The pen's input device, which ghostwriter both writes strokes to and reads triggers from
+    pub(crate) fn input_device(device_model: DeviceModel) -> &'static str {
+        match device_model {
+            DeviceModel::Remarkable2 => "/dev/input/event1",
+            DeviceModel::RemarkablePaperPro => "/dev/input/event2",
+            DeviceModel::Unknown => "/dev/input/event1", // Default to RM2
+        }
     }
 
     pub fn draw_line_screen(&mut self, p1: (i32, i32), p2: (i32, i32)) -> Result<()> {
-        self.draw_line(self.virtual_to_input(p1), self.virtual_to_input(p2))
+        self.draw_line(Self::virtual_to_input(self.device_model, p1), Self::virtual_to_input(self.device_model, p2))
     }
 
     pub fn draw_line(&mut self, (x1, y1): (i32, i32), (x2, y2): (i32, i32)) -> Result<()> {
@@ -98,6 +111,48 @@ impl Pen {
         Ok(())
     }
 
+    /// Go over the dark pixels of a bitmap with the eraser, rubbing out whatever was drawn there
+    pub fn erase_bitmap(&mut self, bitmap: &[Vec<bool>]) -> Result<()> {
+        self.eraser = true;
+        let result = self.draw_bitmap(bitmap);
+        self.eraser = false;
+        result
+    }
+
+    /// Draw one continuous stroke through the given virtual screen points
+    pub fn draw_polyline(&mut self, points: &[(i32, i32)]) -> Result<()> {
+        let Some(&first) = points.first() else {
+            return Ok(());
+        };
+
+        self.pen_up()?;
+        self.goto_xy_virtual(first)?;
+        self.pen_down()?;
+        sleep(Duration::from_millis(1));
+
+        let mut previous = first;
+        for &point in &points[1..] {
+            // Fill in long segments so the tablet sees a smooth stroke
+            let (dx, dy) = (point.0 - previous.0, point.1 - previous.1);
+            let steps = ((((dx * dx + dy * dy) as f32).sqrt() / 2.0).ceil() as i32).max(1);
+            for i in 1..=steps {
+                self.goto_xy_virtual((previous.0 + dx * i / steps, previous.1 + dy * i / steps))?;
+            }
+            previous = point;
+        }
+
+        self.pen_up()?;
+        sleep(Duration::from_millis(1));
+        Ok(())
+    }
+
+    pub fn draw_polylines(&mut self, polylines: &[Vec<(i32, i32)>]) -> Result<()> {
+        for polyline in polylines {
+            self.draw_polyline(polyline)?;
+        }
+        Ok(())
+    }
+
     // fn draw_dot(device: &mut Device, (x, y): (i32, i32)) -> Result<()> {
     //     // trace!("Drawing at ({}, {})", x, y);
     //     goto_xy(device, (x, y))?;
@@ -116,10 +171,19 @@ impl Pen {
     //     Ok(())
     // }
 
+    fn tool(&self) -> u16 {
+        if self.eraser {
+            321 // BTN_TOOL_RUBBER
+        } else {
+            320 // BTN_TOOL_PEN
+        }
+    }
+
     pub fn pen_down(&mut self) -> Result<()> {
+        let tool = self.tool();
         if let Some(device) = &mut self.device {
             device.send_events(&[
-                InputEvent::new(EvdevEventType::KEY.0, 320, 1),           // BTN_TOOL_PEN
+                InputEvent::new(EvdevEventType::KEY.0, tool, 1),          // BTN_TOOL_PEN or BTN_TOOL_RUBBER
                 InputEvent::new(EvdevEventType::KEY.0, 330, 1),           // BTN_TOUCH
                 InputEvent::new(EvdevEventType::ABSOLUTE.0, 24, 2630),    // ABS_PRESSURE (max pressure)
                 InputEvent::new(EvdevEventType::ABSOLUTE.0, 25, 0),       // ABS_DISTANCE
@@ -130,12 +194,13 @@ impl Pen {
     }
 
     pub fn pen_up(&mut self) -> Result<()> {
+        let tool = self.tool();
         if let Some(device) = &mut self.device {
             device.send_events(&[
                 InputEvent::new(EvdevEventType::ABSOLUTE.0, 24, 0),       // ABS_PRESSURE
                 InputEvent::new(EvdevEventType::ABSOLUTE.0, 25, 100),     // ABS_DISTANCE
                 InputEvent::new(EvdevEventType::KEY.0, 330, 0),           // BTN_TOUCH
-                InputEvent::new(EvdevEventType::KEY.0, 320, 0),           // BTN_TOOL_PEN
+                InputEvent::new(EvdevEventType::KEY.0, tool, 0),          // BTN_TOOL_PEN or BTN_TOOL_RUBBER
                 InputEvent::new(EvdevEventType::SYNCHRONIZATION.0, 0, 0), // SYN_REPORT
             ])?;
         }
@@ -143,7 +208,7 @@ impl Pen {
     }
 
     pub fn goto_xy_virtual(&mut self, point: (i32, i32)) -> Result<()> {
-        self.goto_xy(self.virtual_to_input(point))
+        self.goto_xy(Self::virtual_to_input(self.device_model, point))
     }
 
     pub fn goto_xy(&mut self, (x, y): (i32, i32)) -> Result<()> {
@@ -158,37 +223,73 @@ impl Pen {
     }
 
     pub fn max_x_value(&self) -> i32 {
-        match self.device_model {
-            DeviceModel::Remarkable2 => 15725,
-            DeviceModel::RemarkablePaperPro => 11180,
-            DeviceModel::Unknown => 15725, // Default to RM2
-        }
+        Self::input_size(self.device_model).0
     }
 
     pub fn max_y_value(&self) -> i32 {
-        match self.device_model {
-            DeviceModel::Remarkable2 => 20966,
-            DeviceModel::RemarkablePaperPro => 15340,
-            DeviceModel::Unknown => 20966, // Default to RM2
+        Self::input_size(self.device_model).1
+    }
+
+    /// The largest ABS_X and ABS_Y the pen reports
+    fn input_size(device_model: DeviceModel) -> (i32, i32) {
+        match device_model {
+            DeviceModel::Remarkable2 => (15725, 20966),
+            DeviceModel::RemarkablePaperPro => (11180, 15340),
+            DeviceModel::Unknown => (15725, 20966), // Default to RM2
         }
     }
 
-    fn virtual_to_input(&self, (x, y): (i32, i32)) -> (i32, i32) {
+    pub(crate) fn virtual_to_input(device_model: DeviceModel, (x, y): (i32, i32)) -> (i32, i32) {
+        let (max_x, max_y) = Self::input_size(device_model);
+
         // Swap and normalize the coordinates
         let x_normalized = x as f32 / VIRTUAL_WIDTH as f32;
         let y_normalized = y as f32 / VIRTUAL_HEIGHT as f32;
 
-        match self.device_model {
+        match device_model {
             DeviceModel::RemarkablePaperPro => {
-                let x_input = (x_normalized * self.max_x_value() as f32) as i32;
-                let y_input = (y_normalized * self.max_y_value() as f32) as i32;
+                let x_input = (x_normalized * max_x as f32) as i32;
+                let y_input = (y_normalized * max_y as f32) as i32;
                 (x_input, y_input)
             }
             _ => {
-                let x_input = ((1.0 - y_normalized) * self.max_y_value() as f32) as i32;
-                let y_input = (x_normalized * self.max_x_value() as f32) as i32;
+                let x_input = ((1.0 - y_normalized) * max_y as f32) as i32;
+                let y_input = (x_normalized * max_x as f32) as i32;
                 (x_input, y_input)
             }
         }
     }
+
+    /// The inverse of `virtual_to_input`, for reading where the pen is
+    pub(crate) fn input_to_virtual(device_model: DeviceModel, (x, y): (i32, i32)) -> (i32, i32) {
+        let (max_x, max_y) = Self::input_size(device_model);
+
+        match device_model {
+            DeviceModel::RemarkablePaperPro => {
+                let x_virtual = (x as f32 / max_x as f32 * VIRTUAL_WIDTH as f32) as i32;
+                let y_virtual = (y as f32 / max_y as f32 * VIRTUAL_HEIGHT as f32) as i32;
+                (x_virtual, y_virtual)
+            }
+            _ => {
+                let x_virtual = (y as f32 / max_x as f32 * VIRTUAL_WIDTH as f32) as i32;
+                let y_virtual = ((1.0 - x as f32 / max_y as f32) * VIRTUAL_HEIGHT as f32) as i32;
+                (x_virtual, y_virtual)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_to_virtual_undoes_virtual_to_input() {
+        for device_model in [DeviceModel::Remarkable2, DeviceModel::RemarkablePaperPro] {
+            for point in [(0, 0), (100, 900), (384, 512), (700, 40)] {
+                let (x, y) = Pen::input_to_virtual(device_model, Pen::virtual_to_input(device_model, point));
+                assert!((x - point.0).abs() <= 1 && (y - point.1).abs() <= 1, "{:?} came back as {:?}", point, (x, y));
+            }
+        }
+    }
 }
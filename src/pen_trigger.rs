@@ -0,0 +1,279 @@
+use anyhow::Result;
+use evdev::EventType as EvdevEventType;
+use evdev::{Device, InputEvent};
+use log::{debug, info};
+use std::time::{Duration, SystemTime};
+
+use crate::device::DeviceModel;
+use crate::gesture::{Gesture, Trigger};
+use crate::pen::Pen;
+use crate::touch::TriggerArea;
+
+// Event codes
+const ABS_X: u16 = 0;
+const ABS_Y: u16 = 1;
+const BTN_TOOL_PEN: u16 = 320;
+const BTN_TOUCH: u16 = 330;
+const SYN_REPORT: u16 = 0;
+
+/// How long the pen has to hover over a zone
+const HOVER_TIME: Duration = Duration::from_millis(1500);
+/// Smallest circle that counts, so a written "o" doesn't
+const MIN_CIRCLE_SIZE: i32 = 30;
+
+/// Watches the pen for trigger gestures: hovering over a trigger zone, drawing
+/// a circle in one, or not writing for a while after writing something. The pen
+/// is only read, never written, and ghostwriter's own drawing is skipped by
+/// calling `flush` before waiting.
+pub struct PenTrigger {
+    device: Option<Device>,
+    device_model: DeviceModel,
+    zones: Vec<TriggerArea>,
+    hover: bool,
+    circle: bool,
+    idle: Option<Duration>,
+    position: (i32, i32),
+    in_range: bool,
+    touching: bool,
+    /// The stroke being drawn, in virtual screen pixels
+    stroke: Vec<(i32, i32)>,
+    /// The zone the pen is hovering over, since when, and whether that already triggered
+    hovering: Option<(usize, SystemTime, bool)>,
+    /// When the last stroke ended, until the idle trigger fires
+    last_stroke: Option<SystemTime>,
+}
+
+impl PenTrigger {
+    /// Watch for `pen-hover` when `hover` is set and `pen-circle` when `circle` is,
+    /// in the trigger zones in order (the main trigger corner first), and for
+    /// `idle` once no stroke has been drawn for `idle`
+    pub fn new(no_pen: bool, zones: Vec<TriggerArea>, hover: bool, circle: bool, idle: Option<Duration>) -> Self {
+        let device_model = DeviceModel::detect();
+        let device = if no_pen {
+            None
+        } else {
+            Some(Device::open(Pen::input_device(device_model)).unwrap())
+        };
+        info!("Watching the pen for triggers (hover: {}, circle: {}, idle: {:?})", hover, circle, idle);
+
+        Self {
+            device,
+            device_model,
+            zones,
+            hover,
+            circle,
+            idle,
+            position: (0, 0),
+            in_range: false,
+            touching: false,
+            stroke: Vec::new(),
+            hovering: None,
+            last_stroke: None,
+        }
+    }
+
+    /// Throw away pen events that are already waiting, like the strokes of ghostwriter's own drawing
+    pub fn flush(&mut self) -> Result<()> {
+        self.fetch_events()?;
+        self.stroke.clear();
+        self.hovering = None;
+        self.last_stroke = None;
+        Ok(())
+    }
+
+    /// Check for a pen gesture without blocking
+    pub fn poll(&mut self) -> Result<Option<Trigger>> {
+        let mut trigger = None;
+        for event in self.fetch_events()? {
+            if let Some(recognized) = self.process_event(&event) {
+                trigger = trigger.or(Some(recognized));
+            }
+        }
+        let now = SystemTime::now();
+        Ok(trigger.or_else(|| self.check_hover(now)).or_else(|| self.check_idle(now)))
+    }
+
+    fn fetch_events(&mut self) -> Result<Vec<InputEvent>> {
+        let mut events = Vec::new();
+        if let Some(device) = &mut self.device {
+            device.set_nonblocking(true)?;
+            match device.fetch_events() {
+                Ok(fetched) => events.extend(fetched),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(events)
+    }
+
+    fn process_event(&mut self, event: &InputEvent) -> Option<Trigger> {
+        match (event.event_type(), event.code()) {
+            (EvdevEventType::ABSOLUTE, ABS_X) => self.position.0 = event.value(),
+            (EvdevEventType::ABSOLUTE, ABS_Y) => self.position.1 = event.value(),
+            (EvdevEventType::KEY, BTN_TOOL_PEN) => self.in_range = event.value() != 0,
+            (EvdevEventType::KEY, BTN_TOUCH) => self.touching = event.value() != 0,
+            (EvdevEventType::SYNCHRONIZATION, SYN_REPORT) => return self.frame(event.timestamp()),
+            _ => {}
+        }
+        None
+    }
+
+    /// Handle a complete report of where the pen is
+    fn frame(&mut self, time: SystemTime) -> Option<Trigger> {
+        let position = Pen::input_to_virtual(self.device_model, self.position);
+
+        if self.touching {
+            self.stroke.push(position);
+            self.hovering = None;
+            self.last_stroke = None;
+            return None;
+        }
+
+        if !self.stroke.is_empty() {
+            let stroke = std::mem::take(&mut self.stroke);
+            self.last_stroke = Some(time);
+            if self.circle {
+                if let Some(zone) = circle_center(&stroke).and_then(|center| self.zone_at(center)) {
+                    debug!("Pen circle in zone {}", zone);
+                    return Some(Trigger {
+                        gesture: Gesture::PenCircle,
+                        zone,
+                    });
+                }
+            }
+        }
+
+        let zone = if self.in_range && self.hover { self.zone_at(position) } else { None };
+        match (zone, self.hovering) {
+            (Some(zone), Some((hovering, _, _))) if zone == hovering => {}
+            (Some(zone), _) => self.hovering = Some((zone, time, false)),
+            (None, _) => self.hovering = None,
+        }
+        self.check_hover(time)
+    }
+
+    /// Trigger once the pen has stayed over a zone long enough, then not again until it leaves
+    fn check_hover(&mut self, now: SystemTime) -> Option<Trigger> {
+        let (zone, since, triggered) = self.hovering?;
+        if triggered || now.duration_since(since).map_or(true, |hovered| hovered < HOVER_TIME) {
+            return None;
+        }
+        debug!("Pen hovered over zone {}", zone);
+        self.hovering = Some((zone, since, true));
+        Some(Trigger {
+            gesture: Gesture::PenHover,
+            zone,
+        })
+    }
+
+    /// Trigger once, when the pen has been up for the idle time since the last stroke
+    fn check_idle(&mut self, now: SystemTime) -> Option<Trigger> {
+        let idle = self.idle?;
+        let last_stroke = self.last_stroke?;
+        if now.duration_since(last_stroke).map_or(true, |still| still < idle) {
+            return None;
+        }
+        debug!("Pen idle for {:?}", idle);
+        self.last_stroke = None;
+        Some(Trigger {
+            gesture: Gesture::Idle,
+            zone: 0,
+        })
+    }
+
+    fn zone_at(&self, point: (i32, i32)) -> Option<usize> {
+        self.zones.iter().position(|area| area.contains(point))
+    }
+}
+
+/// The center of a stroke if it's a circle: big enough, about as wide as it is
+/// tall, ending near where it started, going all the way around its center, and
+/// staying roughly the same distance from it
+fn circle_center(stroke: &[(i32, i32)]) -> Option<(i32, i32)> {
+    if stroke.len() < 10 {
+        return None;
+    }
+    let (min_x, max_x) = (stroke.iter().map(|p| p.0).min()?, stroke.iter().map(|p| p.0).max()?);
+    let (min_y, max_y) = (stroke.iter().map(|p| p.1).min()?, stroke.iter().map(|p| p.1).max()?);
+    let (width, height) = (max_x - min_x, max_y - min_y);
+    if width.min(height) < MIN_CIRCLE_SIZE || width > height * 2 || height > width * 2 {
+        return None;
+    }
+
+    let center = ((min_x + max_x) as f32 / 2.0, (min_y + max_y) as f32 / 2.0);
+    let (first, last) = (stroke[0], stroke[stroke.len() - 1]);
+    let gap = (((last.0 - first.0).pow(2) + (last.1 - first.1).pow(2)) as f32).sqrt();
+    if gap > width.max(height) as f32 * 0.35 {
+        return None;
+    }
+
+    let angle = |(x, y): (i32, i32)| (y as f32 - center.1).atan2(x as f32 - center.0);
+    let swept: f32 = stroke
+        .windows(2)
+        .map(|pair| {
+            let turn = angle(pair[1]) - angle(pair[0]);
+            // Unwrap across the -pi/pi boundary
+            (turn + std::f32::consts::PI).rem_euclid(std::f32::consts::TAU) - std::f32::consts::PI
+        })
+        .sum();
+    if swept.abs() < std::f32::consts::TAU * 0.85 {
+        return None;
+    }
+
+    let radii: Vec<f32> = stroke
+        .iter()
+        .map(|&(x, y)| ((x as f32 - center.0).powi(2) + (y as f32 - center.1).powi(2)).sqrt())
+        .collect();
+    let mean = radii.iter().sum::<f32>() / radii.len() as f32;
+    let spread = (radii.iter().map(|r| (r - mean).powi(2)).sum::<f32>() / radii.len() as f32).sqrt();
+    if spread > mean * 0.35 {
+        return None;
+    }
+
+    Some((center.0 as i32, center.1 as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points around an ellipse from `start` to `end` radians, with every other point pushed out by `wobble`
+    fn arc(center: (i32, i32), radius: (f32, f32), start: f32, end: f32, wobble: f32) -> Vec<(i32, i32)> {
+        (0..=60)
+            .map(|i| {
+                let angle = start + (end - start) * i as f32 / 60.0;
+                let scale = if i % 2 == 0 { 1.0 } else { 1.0 + wobble };
+                (
+                    center.0 + (radius.0 * scale * angle.cos()) as i32,
+                    center.1 + (radius.1 * scale * angle.sin()) as i32,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_the_center_of_a_drawn_circle() {
+        let (x, y) = circle_center(&arc((300, 400), (60.0, 50.0), 0.0, std::f32::consts::TAU, 0.08)).unwrap();
+        assert!((x - 300).abs() <= 4 && (y - 400).abs() <= 4, "center {:?}", (x, y));
+        // Drawn the other way round, overshooting the start a little
+        assert!(circle_center(&arc((300, 400), (60.0, 60.0), 0.5, -6.5, 0.0)).is_some());
+    }
+
+    #[test]
+    fn ignores_a_written_o() {
+        // Letter sized, like an "o" in the middle of a word
+        assert_eq!(circle_center(&arc((300, 400), (9.0, 11.0), 0.0, std::f32::consts::TAU, 0.05)), None);
+    }
+
+    #[test]
+    fn ignores_strokes_that_are_not_closed_loops() {
+        // A "c"
+        assert_eq!(circle_center(&arc((300, 400), (60.0, 60.0), 0.8, 5.5, 0.0)), None);
+        // A long thin loop, like an underline that turns back on itself
+        assert_eq!(circle_center(&arc((300, 400), (200.0, 30.0), 0.0, std::f32::consts::TAU, 0.0)), None);
+        // A straight line there and back
+        let line: Vec<(i32, i32)> = (0..20).chain((0..20).rev()).map(|i| (200 + i * 10, 400)).collect();
+        assert_eq!(circle_center(&line), None);
+        assert_eq!(circle_center(&[(0, 0), (50, 50)]), None);
+    }
+}
@@ -0,0 +1,221 @@
+use anyhow::Result;
+use log::debug;
+use resvg::usvg::tiny_skia_path::{PathSegment, Point};
+use resvg::usvg::{Group, Node, Path};
+
+use crate::util::parse_svg;
+
+/// A continuous pen stroke in virtual screen coordinates
+pub type Polyline = Vec<(i32, i32)>;
+
+// Curves are flattened into segments roughly this long (in virtual pixels)
+const CURVE_SEGMENT_LENGTH: f32 = 2.0;
+
+/// Turn an SVG into pen strokes: every path (including shapes and text, which
+/// usvg converts to outlines) becomes one or more polylines, ordered to keep
+/// pen-up travel short. Fills and stroke widths are ignored, so a filled shape
+/// comes out as its outline.
+pub fn svg_to_polylines(svg_data: &str, width: u32, height: u32) -> Result<Vec<Polyline>> {
+    let tree = parse_svg(svg_data, width, height)?;
+
+    let mut polylines = Vec::new();
+    collect_group(tree.root(), &mut polylines);
+
+    let polylines = polylines
+        .into_iter()
+        .map(|polyline| to_screen(&polyline, width, height))
+        .filter(|polyline| !polyline.is_empty())
+        .collect::<Vec<_>>();
+    debug!("SVG flattened into {} strokes", polylines.len());
+
+    Ok(order_polylines(polylines))
+}
+
+fn collect_group(group: &Group, polylines: &mut Vec<Vec<Point>>) {
+    for node in group.children() {
+        match node {
+            Node::Group(group) => collect_group(group, polylines),
+            Node::Path(path) => collect_path(path, polylines),
+            Node::Text(text) => collect_group(text.flattened(), polylines),
+            Node::Image(_) => debug!("Skipping embedded image, it can't be drawn with strokes"),
+        }
+    }
+}
+
+fn collect_path(path: &Path, polylines: &mut Vec<Vec<Point>>) {
+    if !path.is_visible() || (path.fill().is_none() && path.stroke().is_none()) {
+        return;
+    }
+
+    let transform = path.abs_transform();
+    let first_new = polylines.len();
+    let mut current: Vec<Point> = Vec::new();
+    let mut start = Point::zero();
+    let mut last = Point::zero();
+
+    for segment in path.data().segments() {
+        match segment {
+            PathSegment::MoveTo(p) => {
+                if current.len() > 1 {
+                    polylines.push(std::mem::take(&mut current));
+                }
+                current.clear();
+                current.push(p);
+                start = p;
+                last = p;
+            }
+            PathSegment::LineTo(p) => {
+                current.push(p);
+                last = p;
+            }
+            PathSegment::QuadTo(p1, p) => {
+                let steps = curve_steps(&[last, p1, p]);
+                for i in 1..=steps {
+                    let t = i as f32 / steps as f32;
+                    let mt = 1.0 - t;
+                    current.push(Point::from_xy(
+                        mt * mt * last.x + 2.0 * mt * t * p1.x + t * t * p.x,
+                        mt * mt * last.y + 2.0 * mt * t * p1.y + t * t * p.y,
+                    ));
+                }
+                last = p;
+            }
+            PathSegment::CubicTo(p1, p2, p) => {
+                let steps = curve_steps(&[last, p1, p2, p]);
+                for i in 1..=steps {
+                    let t = i as f32 / steps as f32;
+                    let mt = 1.0 - t;
+                    current.push(Point::from_xy(
+                        mt * mt * mt * last.x + 3.0 * mt * mt * t * p1.x + 3.0 * mt * t * t * p2.x + t * t * t * p.x,
+                        mt * mt * mt * last.y + 3.0 * mt * mt * t * p1.y + 3.0 * mt * t * t * p2.y + t * t * t * p.y,
+                    ));
+                }
+                last = p;
+            }
+            PathSegment::Close => {
+                current.push(start);
+                last = start;
+            }
+        }
+    }
+    if current.len() > 1 {
+        polylines.push(current);
+    }
+
+    for polyline in &mut polylines[first_new..] {
+        transform.map_points(polyline);
+    }
+}
+
+/// Pick enough steps that each flattened piece of the curve is short, using the
+/// control polygon length as an upper bound on the curve length
+fn curve_steps(points: &[Point]) -> u32 {
+    let length: f32 = points.windows(2).map(|pair| pair[0].distance(pair[1])).sum();
+    ((length / CURVE_SEGMENT_LENGTH).ceil() as u32).clamp(1, 64)
+}
+
+fn to_screen(points: &[Point], width: u32, height: u32) -> Polyline {
+    let mut polyline: Polyline = Vec::with_capacity(points.len());
+    for point in points {
+        let x = (point.x.round() as i32).clamp(0, width as i32 - 1);
+        let y = (point.y.round() as i32).clamp(0, height as i32 - 1);
+        if polyline.last() != Some(&(x, y)) {
+            polyline.push((x, y));
+        }
+    }
+    polyline
+}
+
+/// Greedy nearest-neighbor ordering. Each next stroke is whichever one starts
+/// (or, reversed, ends) closest to where the pen was lifted.
+pub fn order_polylines(mut remaining: Vec<Polyline>) -> Vec<Polyline> {
+    let mut ordered = Vec::with_capacity(remaining.len());
+    let mut position = (0, 0);
+
+    while !remaining.is_empty() {
+        let mut best_index = 0;
+        let mut best_distance = i64::MAX;
+        let mut best_reversed = false;
+
+        for (index, polyline) in remaining.iter().enumerate() {
+            let start_distance = distance_squared(position, polyline[0]);
+            let end_distance = distance_squared(position, polyline[polyline.len() - 1]);
+            if start_distance < best_distance {
+                best_index = index;
+                best_distance = start_distance;
+                best_reversed = false;
+            }
+            if end_distance < best_distance {
+                best_index = index;
+                best_distance = end_distance;
+                best_reversed = true;
+            }
+        }
+
+        let mut polyline = remaining.swap_remove(best_index);
+        if best_reversed {
+            polyline.reverse();
+        }
+        position = polyline[polyline.len() - 1];
+        ordered.push(polyline);
+    }
+
+    ordered
+}
+
+fn distance_squared((x1, y1): (i32, i32), (x2, y2): (i32, i32)) -> i64 {
+    let dx = (x2 - x1) as i64;
+    let dy = (y2 - y1) as i64;
+    dx * dx + dy * dy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strokes(body: &str) -> Vec<Polyline> {
+        let svg = format!(r#"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="200">{}</svg>"#, body);
+        svg_to_polylines(&svg, 200, 200).unwrap()
+    }
+
+    #[test]
+    fn outlines_filled_shapes() {
+        assert_eq!(
+            strokes(r#"<rect x="10" y="20" width="30" height="40" fill="black"/>"#),
+            vec![vec![(10, 20), (40, 20), (40, 60), (10, 60), (10, 20)]]
+        );
+        // Nothing to draw
+        assert!(strokes(r#"<rect x="10" y="20" width="30" height="40" fill="none"/>"#).is_empty());
+    }
+
+    #[test]
+    fn flattens_curves_into_short_segments() {
+        let curve = strokes(r#"<path d="M 0 100 Q 50 0 100 100" stroke="black" fill="none"/>"#).remove(0);
+        assert_eq!(curve.first(), Some(&(0, 100)));
+        assert_eq!(curve.last(), Some(&(100, 100)));
+        // The top of the curve, halfway between the ends and the control point
+        assert!(curve.contains(&(50, 50)), "{:?}", curve);
+        // Pieces about CURVE_SEGMENT_LENGTH long, give or take rounding to whole pixels
+        assert!(curve.windows(2).all(|pair| distance_squared(pair[0], pair[1]) <= 16), "{:?}", curve);
+    }
+
+    #[test]
+    fn applies_transforms_and_splits_subpaths() {
+        assert_eq!(
+            strokes(r#"<g transform="translate(5 10)"><path d="M 0 0 L 20 0 M 50 50 L 50 70" stroke="black"/></g>"#),
+            vec![vec![(5, 10), (25, 10)], vec![(55, 60), (55, 80)]]
+        );
+    }
+
+    #[test]
+    fn orders_strokes_by_the_nearest_next_start() {
+        let far = vec![(100, 100), (120, 100)];
+        let near = vec![(5, 5), (10, 5)];
+        // Closest to the end of `near` by its end, so it's drawn backwards
+        let backwards = vec![(30, 5), (12, 5)];
+        assert_eq!(
+            order_polylines(vec![far.clone(), backwards, near.clone()]),
+            vec![near, vec![(12, 5), (30, 5)], far]
+        );
+    }
+}
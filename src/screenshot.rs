@@ -285,6 +285,10 @@ impl Screenshot {
         Ok(())
     }
 
+    pub fn png_data(&self) -> &[u8] {
+        &self.data
+    }
+
     pub fn base64(&self) -> Result<String> {
         let base64_image = general_purpose::STANDARD.encode(&self.data);
         Ok(base64_image)
@@ -0,0 +1,156 @@
+use anyhow::Result;
+use log::{debug, info};
+use serde_json::json;
+use serde_json::Value as json;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::embedded_assets::try_load_config;
+use crate::llm_engine::{ToolCallback, ToolOutput};
+
+/// How long an external command gets before it's killed
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Load `tool_<name>.json`, filling in an empty parameter schema for tools that take no arguments
+pub fn load_tool_definition(name: &str) -> Result<json> {
+    let filename = format!("tool_{}.json", name);
+    let raw = try_load_config(&filename).ok_or_else(|| anyhow::anyhow!("Unknown tool '{}', no {} found", name, filename))?;
+    let mut definition = serde_json::from_str::<json>(raw.as_str()).map_err(|e| anyhow::anyhow!("Tool file '{}' is not valid JSON: {}", filename, e))?;
+
+    if definition["parameters"].is_null() {
+        definition["parameters"] = json!({
+            "type": "object",
+            "properties": {}
+        });
+    }
+
+    Ok(definition)
+}
+
+/// Run a tool's `external_command` through the shell. The tool arguments are
+/// written to stdin as JSON and stdout becomes the tool result.
+pub fn run_external_command(command: &str, arguments: &json) -> ToolOutput {
+    run_with_timeout(command, arguments, COMMAND_TIMEOUT)
+}
+
+fn run_with_timeout(command: &str, arguments: &json, timeout: Duration) -> ToolOutput {
+    info!("Running external command: {}", command);
+
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => return ToolOutput::Error(format!("Failed to start '{}': {}", command, e)),
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        if let Err(e) = stdin.write_all(arguments.to_string().as_bytes()) {
+            debug!("Failed to write arguments to '{}': {}", command, e);
+        }
+    }
+
+    // Read both pipes while waiting, so a chatty command can't fill one up and stall
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() >= timeout => {
+                // Whatever it started may still hold the pipes open, so don't wait for the readers
+                child.kill().ok();
+                child.wait().ok();
+                return ToolOutput::Error(format!("'{}' timed out after {:?}", command, timeout));
+            }
+            Ok(None) => sleep(Duration::from_millis(20)),
+            Err(e) => return ToolOutput::Error(format!("Failed to run '{}': {}", command, e)),
+        }
+    };
+
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    debug!("External command stdout: {}", stdout);
+    debug!("External command stderr: {}", stderr);
+
+    if status.success() {
+        ToolOutput::Text(stdout)
+    } else {
+        ToolOutput::Error(format!(
+            "'{}' exited with {}\nstdout:\n{}\nstderr:\n{}",
+            command,
+            status.code().map_or("a signal".to_string(), |code| format!("code {}", code)),
+            stdout,
+            stderr
+        ))
+    }
+}
+
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<String> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut output).ok();
+        }
+        String::from_utf8_lossy(&output).to_string()
+    })
+}
+
+pub fn external_command_callback(command: &str) -> ToolCallback {
+    let command = command.to_string();
+    Box::new(move |arguments: json| run_external_command(&command, &arguments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(command: &str) -> ToolOutput {
+        run_with_timeout(command, &json!({ "text": "hello" }), Duration::from_secs(5))
+    }
+
+    #[test]
+    fn passes_the_arguments_on_stdin() {
+        match run("cat") {
+            ToolOutput::Text(text) => assert_eq!(text, r#"{"text":"hello"}"#),
+            output => panic!("Expected text, got {:?}", output),
+        }
+    }
+
+    #[test]
+    fn reports_a_failed_command() {
+        match run("echo partial; echo broken >&2; exit 3") {
+            ToolOutput::Error(error) => {
+                assert!(error.contains("exited with code 3"), "{}", error);
+                assert!(error.contains("stdout:\npartial\n"), "{}", error);
+                assert!(error.contains("stderr:\nbroken\n"), "{}", error);
+            }
+            output => panic!("Expected an error, got {:?}", output),
+        }
+    }
+
+    #[test]
+    fn ignores_stderr_from_a_successful_command() {
+        match run("echo noise >&2; echo done") {
+            ToolOutput::Text(text) => assert_eq!(text, "done\n"),
+            output => panic!("Expected text, got {:?}", output),
+        }
+    }
+
+    #[test]
+    fn kills_a_command_that_takes_too_long() {
+        let started = Instant::now();
+        match run_with_timeout("sleep 10", &json!({}), Duration::from_millis(200)) {
+            ToolOutput::Error(error) => assert!(error.contains("timed out after 200ms"), "{}", error),
+            output => panic!("Expected an error, got {:?}", output),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -4,11 +4,12 @@ use evdev::{Device, InputEvent};
 use log::{debug, info, trace};
 
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use crate::device::DeviceModel;
+use crate::gesture::{GestureRecognizer, Trigger};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub enum TriggerCorner {
     UpperRight,
     UpperLeft,
@@ -29,6 +30,39 @@ impl TriggerCorner {
             )),
         }
     }
+
+    /// Whether a point in virtual screen pixels is in this corner's 68x68 trigger zone
+    pub fn contains(&self, (x, y): (i32, i32)) -> bool {
+        const CORNER_SIZE: i32 = 68; // Size of the trigger zone (68x68 pixels)
+
+        match self {
+            TriggerCorner::UpperRight => x > VIRTUAL_WIDTH as i32 - CORNER_SIZE && y < CORNER_SIZE,
+            TriggerCorner::UpperLeft => x < CORNER_SIZE && y < CORNER_SIZE,
+            TriggerCorner::LowerRight => x > VIRTUAL_WIDTH as i32 - CORNER_SIZE && y > VIRTUAL_HEIGHT as i32 - CORNER_SIZE,
+            TriggerCorner::LowerLeft => x < CORNER_SIZE && y > VIRTUAL_HEIGHT as i32 - CORNER_SIZE,
+        }
+    }
+}
+
+/// Where a tap has to land to trigger, in virtual screen pixels
+#[derive(Debug, Clone)]
+pub enum TriggerArea {
+    Corner(TriggerCorner),
+    Rect { x: i32, y: i32, width: i32, height: i32 },
+}
+
+impl TriggerArea {
+    pub fn contains(&self, (x, y): (i32, i32)) -> bool {
+        match self {
+            TriggerArea::Corner(corner) => corner.contains((x, y)),
+            TriggerArea::Rect {
+                x: left,
+                y: top,
+                width,
+                height,
+            } => x >= *left && x < left + width && y >= *top && y < top + height,
+        }
+    }
 }
 
 // Output dimensions remain the same for both devices
@@ -45,15 +79,30 @@ const ABS_MT_POSITION_Y: u16 = 54;
 // const ABS_MT_TOOL_TYPE: u16 = 55;
 const ABS_MT_TRACKING_ID: u16 = 57;
 const ABS_MT_PRESSURE: u16 = 58;
+const SYN_REPORT: u16 = 0;
+
+const MAX_SLOTS: usize = 16;
+
+/// One multitouch slot, as last reported by the touchscreen
+#[derive(Debug, Clone, Copy, Default)]
+struct Slot {
+    active: bool,
+    /// Whether the recognizer has been told about this contact
+    reported: bool,
+    x: i32,
+    y: i32,
+}
 
 pub struct Touch {
     device: Option<Device>,
     device_model: DeviceModel,
-    trigger_corner: TriggerCorner,
+    recognizer: GestureRecognizer,
+    slots: [Slot; MAX_SLOTS],
+    slot: usize,
 }
 
 impl Touch {
-    pub fn new(no_touch: bool, trigger_corner: TriggerCorner) -> Self {
+    pub fn new(no_touch: bool, recognizer: GestureRecognizer) -> Self {
         let device_model = DeviceModel::detect();
         info!("Touch using device model: {}", device_model.name());
 
@@ -68,40 +117,93 @@ impl Touch {
         Self {
             device,
             device_model,
-            trigger_corner,
+            recognizer,
+            slots: [Slot::default(); MAX_SLOTS],
+            slot: 0,
         }
     }
 
-    pub fn wait_for_trigger(&mut self) -> Result<()> {
-        let mut position_x = 0;
-        let mut position_y = 0;
+    pub fn wait_for_gesture(&mut self) -> Result<Trigger> {
         loop {
-            // Store events in a temporary vector to avoid borrowing issues
-            let mut events_to_process = Vec::new();
-            if let Some(device) = &mut self.device {
-                for event in device.fetch_events()? {
-                    events_to_process.push(event);
-                }
+            // Block on the touchscreen unless a corner tap is waiting to see if a second one follows
+            let waiting = self.recognizer.is_waiting();
+            if let Some(gesture) = self.read_gestures(!waiting)? {
+                return Ok(gesture);
+            }
+            if waiting || self.device.is_none() {
+                sleep(Duration::from_millis(20));
             }
+        }
+    }
 
-            // Process the events after releasing the mutable borrow
-            for event in events_to_process {
-                if event.code() == ABS_MT_POSITION_X {
-                    position_x = event.value();
-                }
-                if event.code() == ABS_MT_POSITION_Y {
-                    position_y = event.value();
+    /// Check for a gesture without blocking, only looking at the events
+    /// that arrived since the last call
+    pub fn poll_gesture(&mut self) -> Result<Option<Trigger>> {
+        self.read_gestures(false)
+    }
+
+    fn read_gestures(&mut self, block: bool) -> Result<Option<Trigger>> {
+        // Store events in a temporary vector to avoid borrowing issues
+        let mut events_to_process = Vec::new();
+        if let Some(device) = &mut self.device {
+            device.set_nonblocking(!block)?;
+            match device.fetch_events() {
+                Ok(events) => events_to_process.extend(events),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        // Process the events after releasing the mutable borrow
+        let mut gesture = None;
+        for event in events_to_process {
+            if let Some(recognized) = self.process_event(&event) {
+                gesture = gesture.or(Some(recognized));
+            }
+        }
+        Ok(gesture.or_else(|| self.recognizer.expire(SystemTime::now())))
+    }
+
+    /// Track the multitouch slots, passing each finger's touch, movement, and release to the
+    /// recognizer once a frame is complete. Returns a gesture when one is recognized.
+    fn process_event(&mut self, event: &InputEvent) -> Option<Trigger> {
+        if event.event_type() == EvdevEventType::ABSOLUTE {
+            let slot = &mut self.slots[self.slot];
+            match event.code() {
+                ABS_MT_SLOT => self.slot = (event.value().max(0) as usize).min(MAX_SLOTS - 1),
+                ABS_MT_TRACKING_ID => slot.active = event.value() != -1,
+                ABS_MT_POSITION_X => slot.x = event.value(),
+                ABS_MT_POSITION_Y => slot.y = event.value(),
+                _ => {}
+            }
+            return None;
+        }
+        if event.event_type() != EvdevEventType::SYNCHRONIZATION || event.code() != SYN_REPORT {
+            return None;
+        }
+
+        let mut gesture = None;
+        for index in 0..MAX_SLOTS {
+            let slot = self.slots[index];
+            let position = self.input_to_virtual((slot.x, slot.y));
+            match (slot.reported, slot.active) {
+                (false, true) => {
+                    trace!("Touch {} down at {:?}", index, position);
+                    self.recognizer.touch_down(index, position, event.timestamp());
                 }
-                if event.code() == ABS_MT_TRACKING_ID && event.value() == -1 {
-                    let (x, y) = self.input_to_virtual((position_x, position_y));
-                    debug!("Touch release detected at ({}, {}) normalized ({}, {})", position_x, position_y, x, y);
-                    if self.is_in_trigger_zone(x, y) {
-                        debug!("Touch release in target zone!");
-                        return Ok(());
-                    }
+                (true, true) => self.recognizer.touch_move(index, position),
+                (true, false) => {
+                    debug!("Touch release detected at ({}, {}) normalized {:?}", slot.x, slot.y, position);
+                    gesture = gesture.or(self.recognizer.touch_up(index, event.timestamp()));
                 }
+                (false, false) => {}
             }
+            self.slots[index].reported = slot.active;
+        }
+        if let Some(trigger) = &gesture {
+            debug!("Recognized {} in zone {}", trigger.gesture.name(), trigger.zone);
         }
+        gesture
     }
 
     pub fn touch_start(&mut self, xy: (i32, i32)) -> Result<()> {
@@ -161,17 +263,6 @@ impl Touch {
         Ok(())
     }
 
-    fn is_in_trigger_zone(&self, x: i32, y: i32) -> bool {
-        const CORNER_SIZE: i32 = 68; // Size of the trigger zone (68x68 pixels)
-
-        match self.trigger_corner {
-            TriggerCorner::UpperRight => x > VIRTUAL_WIDTH as i32 - CORNER_SIZE && y < CORNER_SIZE,
-            TriggerCorner::UpperLeft => x < CORNER_SIZE && y < CORNER_SIZE,
-            TriggerCorner::LowerRight => x > VIRTUAL_WIDTH as i32 - CORNER_SIZE && y > VIRTUAL_HEIGHT as i32 - CORNER_SIZE,
-            TriggerCorner::LowerLeft => x < CORNER_SIZE && y > VIRTUAL_HEIGHT as i32 - CORNER_SIZE,
-        }
-    }
-
     fn screen_width(&self) -> u32 {
         match self.device_model {
             DeviceModel::Remarkable2 => 1404,
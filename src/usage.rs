@@ -0,0 +1,197 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::llm_engine::Usage;
+
+/// What a model costs, in US dollars per million tokens
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+}
+
+impl ModelPrice {
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.input_tokens as f64 * self.input + usage.output_tokens as f64 * self.output) / 1_000_000.0
+    }
+}
+
+/// Starting prices for the commonly used models; override or extend them with
+/// `[prices."model-name"]` in ~/.ghostwriter.toml
+pub fn default_prices() -> HashMap<String, ModelPrice> {
+    [
+        ("claude-sonnet-4-0", 3.0, 15.0),
+        ("claude-opus-4-0", 15.0, 75.0),
+        ("claude-3-5-haiku", 0.8, 4.0),
+        ("gpt-4o", 2.5, 10.0),
+        ("gpt-4o-mini", 0.15, 0.6),
+        ("gpt-4.1", 2.0, 8.0),
+        ("o4-mini", 1.1, 4.4),
+        ("gemini-2.0-flash", 0.1, 0.4),
+        ("gemini-2.5-pro", 1.25, 10.0),
+    ]
+    .into_iter()
+    .map(|(model, input, output)| (model.to_string(), ModelPrice { input, output }))
+    .collect()
+}
+
+/// The price for a model, falling back to the longest listed name it starts
+/// with so dated versions like `gpt-4o-2024-08-06` still match `gpt-4o`
+pub fn price_for<'a>(prices: &'a HashMap<String, ModelPrice>, model: &str) -> Option<&'a ModelPrice> {
+    prices.get(model).or_else(|| {
+        prices
+            .iter()
+            .filter(|(name, _)| model.starts_with(name.as_str()))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, price)| price)
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LedgerEntry {
+    timestamp: u64,
+    date: String,
+    model: String,
+    input_tokens: u64,
+    output_tokens: u64,
+    thinking_tokens: u64,
+    cost: Option<f64>,
+}
+
+/// Append-only record of every trigger's usage, one JSON object per line
+pub struct Ledger {
+    path: PathBuf,
+}
+
+impl Ledger {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// ~/.ghostwriter_usage.jsonl
+    pub fn default_path() -> Result<PathBuf> {
+        let home = std::env::var("HOME").map_err(|_| anyhow::anyhow!("HOME environment variable not set"))?;
+        Ok(std::path::Path::new(&home).join(".ghostwriter_usage.jsonl"))
+    }
+
+    pub fn record(&self, model: &str, usage: &Usage, cost: Option<f64>) -> Result<()> {
+        let (timestamp, date) = now();
+        let entry = LedgerEntry {
+            timestamp,
+            date,
+            model: model.to_string(),
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            thinking_tokens: usage.thinking_tokens,
+            cost,
+        };
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| anyhow::anyhow!("Failed to open usage ledger {:?}: {}", self.path, e))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        Ok(())
+    }
+
+    /// Dollars spent so far today (UTC). Entries without a known price count as free.
+    pub fn total_today(&self) -> Result<f64> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0.0),
+            Err(e) => return Err(anyhow::anyhow!("Failed to read usage ledger {:?}: {}", self.path, e)),
+        };
+
+        let (_, today) = now();
+        Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str::<LedgerEntry>(line).ok())
+            .filter(|entry| entry.date == today)
+            .filter_map(|entry| entry.cost)
+            // Not sum(), which gives -0.0 for nothing spent
+            .fold(0.0, |total, cost| total + cost))
+    }
+}
+
+/// Seconds since the epoch and the UTC date as YYYY-MM-DD
+fn now() -> (u64, String) {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days((timestamp / 86_400) as i64);
+    (timestamp, format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+/// Convert days since 1970-01-01 into a (year, month, day) date
+/// (Howard Hinnant's `civil_from_days`)
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_days_to_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+        assert_eq!(civil_from_days(19_723), (2024, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(20_744), (2026, 10, 18));
+    }
+
+    #[test]
+    fn prices_dated_versions_by_the_longest_prefix() {
+        let prices = default_prices();
+        assert_eq!(price_for(&prices, "gpt-4o").map(|price| price.input), Some(2.5));
+        assert_eq!(price_for(&prices, "gpt-4o-mini-2024-07-18").map(|price| price.input), Some(0.15));
+        assert_eq!(price_for(&prices, "gpt-4o-2024-08-06").map(|price| price.input), Some(2.5));
+        assert_eq!(price_for(&prices, "llama3.2-vision"), None);
+
+        let usage = Usage {
+            input_tokens: 1_000_000,
+            output_tokens: 500_000,
+            thinking_tokens: 0,
+        };
+        assert_eq!(prices["gpt-4o"].cost(&usage), 7.5);
+    }
+
+    #[test]
+    fn totals_todays_priced_entries() {
+        let path = std::env::temp_dir().join(format!("ghostwriter-usage-{}.jsonl", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let ledger = Ledger::new(&path);
+        let usage = Usage::default();
+
+        ledger.record("llama3.2-vision", &usage, None).unwrap();
+        let nothing = ledger.total_today().unwrap();
+        ledger.record("gpt-4o", &usage, Some(0.25)).unwrap();
+        ledger.record("gpt-4o", &usage, Some(0.5)).unwrap();
+        std::fs::write(
+            &path,
+            std::fs::read_to_string(&path).unwrap()
+                + r#"{"timestamp":0,"date":"1970-01-01","model":"gpt-4o","input_tokens":0,"output_tokens":0,"thinking_tokens":0,"cost":9.0}"#
+                + "\n",
+        )
+        .unwrap();
+        let total = ledger.total_today().unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(format!("{:.4}", nothing), "0.0000");
+        assert_eq!(total, 0.75);
+    }
+}
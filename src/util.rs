@@ -15,7 +15,8 @@ use crate::embedded_assets::get_uinput_module_data;
 
 pub type OptionMap = HashMap<String, String>;
 
-pub fn svg_to_bitmap(svg_data: &str, width: u32, height: u32) -> Result<Vec<Vec<bool>>> {
+/// Parse SVG data, falling back to an "ERROR!" message if the SVG is invalid
+pub fn parse_svg(svg_data: &str, width: u32, height: u32) -> Result<Tree> {
     let mut opt = Options::default();
     let mut fontdb = fontdb::Database::new();
     fontdb.load_system_fonts();
@@ -33,6 +34,12 @@ pub fn svg_to_bitmap(svg_data: &str, width: u32, height: u32) -> Result<Vec<Vec<
         }
     };
 
+    Ok(tree)
+}
+
+pub fn svg_to_bitmap(svg_data: &str, width: u32, height: u32) -> Result<Vec<Vec<bool>>> {
+    let tree = parse_svg(svg_data, width, height)?;
+
     let mut pixmap = Pixmap::new(width, height).unwrap();
     render(&tree, usvg::Transform::default(), &mut pixmap.as_mut());
 
@@ -61,6 +68,77 @@ pub fn write_bitmap_to_file(bitmap: &[Vec<bool>], filename: &str) -> Result<()>
     Ok(())
 }
 
+/// Guess whether two screenshots show the same page. Most of the ink from the
+/// previous screenshot should still be there if the user stayed on the page,
+/// since both the user and the assistant only ever add to it.
+pub fn is_same_page(previous_png: &[u8], current_png: &[u8]) -> Result<bool> {
+    let previous = image::load_from_memory(previous_png)?.to_luma8();
+    let current = image::load_from_memory(current_png)?.to_luma8();
+
+    if previous.dimensions() != current.dimensions() {
+        return Ok(false);
+    }
+
+    let mut previous_ink = 0;
+    let mut current_ink = 0;
+    let mut kept_ink = 0;
+    for (previous_pixel, current_pixel) in previous.pixels().zip(current.pixels()) {
+        if current_pixel[0] < 128 {
+            current_ink += 1;
+        }
+        if previous_pixel[0] < 128 {
+            previous_ink += 1;
+            if current_pixel[0] < 128 {
+                kept_ink += 1;
+            }
+        }
+    }
+
+    // Any page with ink on it would pass for a blank one, so only another blank page does
+    if previous_ink == 0 {
+        return Ok(current_ink == 0);
+    }
+
+    let kept_ratio = kept_ink as f32 / previous_ink as f32;
+    debug!("Kept {:.1}% of the previous ink", kept_ratio * 100.0);
+    Ok(kept_ratio > 0.8)
+}
+
+/// How much of the page, in percent, went from ink to blank or blank to ink
+/// between two screenshots. Screenshots of different sizes count as all changed.
+pub fn changed_percent(previous_png: &[u8], current_png: &[u8]) -> Result<f64> {
+    let previous = image::load_from_memory(previous_png)?.to_luma8();
+    let current = image::load_from_memory(current_png)?.to_luma8();
+
+    if previous.dimensions() != current.dimensions() {
+        return Ok(100.0);
+    }
+
+    let changed = previous
+        .pixels()
+        .zip(current.pixels())
+        .filter(|(previous_pixel, current_pixel)| (previous_pixel[0] < 128) != (current_pixel[0] < 128))
+        .count();
+    Ok(changed as f64 * 100.0 / (previous.width() * previous.height()).max(1) as f64)
+}
+
+/// Replace embedded images (data URLs and long base64 strings) with a short
+/// placeholder, so recorded requests stay readable
+pub fn strip_images(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::String(text) => {
+            let data = text.strip_prefix("data:").and_then(|rest| rest.split_once(";base64,")).map(|(_, data)| data);
+            let is_base64 = |data: &str| data.len() > 1000 && data.bytes().all(|b| b.is_ascii_alphanumeric() || b"+/=".contains(&b));
+            if let Some(data) = data.or(Some(text.as_str()).filter(|text| is_base64(text))) {
+                *text = format!("<image: {} bytes base64>", data.len());
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(strip_images),
+        serde_json::Value::Object(fields) => fields.values_mut().for_each(strip_images),
+        _ => {}
+    }
+}
+
 pub fn option_or_env(options: &OptionMap, key: &str, env_key: &str) -> String {
     let option = options.get(key);
     if let Some(value) = option {
@@ -128,3 +206,38 @@ pub fn setup_uinput() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, ImageFormat, Luma};
+    use std::io::Cursor;
+
+    /// A white page with black rectangles, each x, y, width, height
+    fn page(rects: &[(u32, u32, u32, u32)]) -> Vec<u8> {
+        let mut image = GrayImage::from_pixel(100, 100, Luma([255]));
+        for &(x, y, w, h) in rects {
+            for py in y..y + h {
+                for px in x..x + w {
+                    image.put_pixel(px, py, Luma([0]));
+                }
+            }
+        }
+        let mut png = Vec::new();
+        image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png).unwrap();
+        png
+    }
+
+    #[test]
+    fn a_page_keeps_its_ink() {
+        let previous = page(&[(10, 10, 20, 20)]);
+        assert!(is_same_page(&previous, &page(&[(10, 10, 20, 20), (50, 50, 30, 30)])).unwrap());
+        assert!(!is_same_page(&previous, &page(&[(50, 50, 30, 30)])).unwrap());
+    }
+
+    #[test]
+    fn a_blank_page_only_matches_another_blank_page() {
+        assert!(is_same_page(&page(&[]), &page(&[])).unwrap());
+        assert!(!is_same_page(&page(&[]), &page(&[(50, 50, 30, 30)])).unwrap());
+    }
+}
@@ -0,0 +1,165 @@
+//! Replays recorded provider exchanges through each engine's full agentic loop,
+//! without a network connection or an API key
+
+use ghostwriter::llm_engine::error::EngineError;
+use ghostwriter::llm_engine::{create_engine, ToolOutput, Usage};
+use ghostwriter::util::OptionMap;
+use serde_json::json;
+use serde_json::Value as json;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Not a real screenshot, it only has to hash the same as when it was recorded
+const IMAGE: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNk+M9QDwADhgGAWjR9awAAAABJRU5ErkJggg==";
+
+/// Ask the engine what's on the page, with a `lookup` tool that loops and a
+/// `draw_text` tool that ends the turn. Returns every tool call and the result.
+fn run(engine_name: &str, model: &str, cancel_during_lookup: bool) -> (Vec<(String, json)>, anyhow::Result<Usage>) {
+    let options = OptionMap::from([
+        ("model".to_string(), model.to_string()),
+        ("api_key".to_string(), "test".to_string()),
+        // Where the exchanges were recorded, part of the cassette key. Replay never connects to it.
+        ("base_url".to_string(), "http://127.0.0.1:18450".to_string()),
+        (
+            "cassette".to_string(),
+            format!("{}/tests/fixtures/cassettes/{}.jsonl", env!("CARGO_MANIFEST_DIR"), engine_name),
+        ),
+    ]);
+    let mut engine = create_engine(engine_name, &options).unwrap();
+    let cancel = Arc::new(AtomicBool::new(false));
+    engine.set_cancel_flag(cancel.clone());
+
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let lookup_calls = calls.clone();
+    engine.register_tool(
+        "lookup",
+        json!({
+            "name": "lookup",
+            "description": "Look up a fact",
+            "parameters": { "type": "object", "properties": { "query": { "type": "string" } }, "required": ["query"] },
+            "next_action": "loop",
+        }),
+        Box::new(move |arguments| {
+            lookup_calls.borrow_mut().push(("lookup".to_string(), arguments));
+            if cancel_during_lookup {
+                cancel.store(true, Ordering::SeqCst);
+            }
+            ToolOutput::Text("forty-two".to_string())
+        }),
+    );
+    let draw_calls = calls.clone();
+    engine.register_tool(
+        "draw_text",
+        json!({
+            "name": "draw_text",
+            "description": "Write text on the page",
+            "parameters": { "type": "object", "properties": { "text": { "type": "string" } }, "required": ["text"] },
+        }),
+        Box::new(move |arguments| {
+            draw_calls.borrow_mut().push(("draw_text".to_string(), arguments));
+            ToolOutput::Text("drawn".to_string())
+        }),
+    );
+
+    engine.add_text_content("Answer the question on the page.");
+    engine.add_image_content(IMAGE);
+    let result = engine.execute();
+
+    let calls = calls.borrow().clone();
+    (calls, result)
+}
+
+fn replay(engine_name: &str, model: &str) -> (Vec<(String, json)>, Usage) {
+    let (calls, result) = run(engine_name, model, false);
+    (calls, result.unwrap())
+}
+
+fn assert_calls(calls: &[(String, json)]) {
+    assert_eq!(
+        calls,
+        [
+            ("lookup".to_string(), json!({ "query": "answer" })),
+            ("draw_text".to_string(), json!({ "text": "42" })),
+        ]
+    );
+}
+
+#[test]
+fn anthropic() {
+    let (calls, usage) = replay("anthropic", "claude-sonnet-4-5");
+    assert_calls(&calls);
+    assert_eq!(
+        usage,
+        Usage {
+            input_tokens: 260,
+            output_tokens: 45,
+            thinking_tokens: 0,
+        }
+    );
+}
+
+#[test]
+fn openai() {
+    let (calls, usage) = replay("openai", "gpt-4o");
+    assert_calls(&calls);
+    assert_eq!(
+        usage,
+        Usage {
+            input_tokens: 250,
+            output_tokens: 45,
+            thinking_tokens: 8,
+        }
+    );
+}
+
+#[test]
+fn google() {
+    let (calls, usage) = replay("google", "gemini-2.5-flash");
+    assert_calls(&calls);
+    assert_eq!(
+        usage,
+        Usage {
+            input_tokens: 250,
+            output_tokens: 50,
+            thinking_tokens: 5,
+        }
+    );
+}
+
+#[test]
+fn openai_responses() {
+    let (calls, usage) = replay("openai-responses", "gpt-5");
+    assert_calls(&calls);
+    assert_eq!(
+        usage,
+        Usage {
+            input_tokens: 250,
+            output_tokens: 45,
+            thinking_tokens: 12,
+        }
+    );
+}
+
+#[test]
+fn ollama_falls_back_to_prompted_tools() {
+    // The cassette answers the native request with "does not support tools", then the emulated ones
+    let (calls, usage) = replay("ollama", "llama3.2-vision");
+    assert_calls(&calls);
+    assert_eq!(
+        usage,
+        Usage {
+            input_tokens: 250,
+            output_tokens: 45,
+            thinking_tokens: 0,
+        }
+    );
+}
+
+#[test]
+fn cancelling_stops_before_the_next_tool() {
+    let (calls, result) = run("anthropic", "claude-sonnet-4-5", true);
+    assert_eq!(calls, [("lookup".to_string(), json!({ "query": "answer" }))]);
+    assert!(matches!(result.unwrap_err().downcast_ref(), Some(EngineError::Cancelled)));
+}
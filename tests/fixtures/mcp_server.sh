@@ -0,0 +1,19 @@
+#!/bin/sh
+# A tiny MCP server over stdio for tests: one "echo" tool that answers with its
+# arguments. Requests arrive with their keys sorted, so the id comes first.
+echo "mcp fixture started" >&2
+while read -r line; do
+    id=$(printf '%s' "$line" | sed -n 's/^{"id":\([0-9]*\),.*/\1/p')
+    case "$line" in
+        *'"method":"initialize"'*)
+            printf '{"jsonrpc":"2.0","id":%s,"result":{"protocolVersion":"2025-06-18","capabilities":{"tools":{}},"serverInfo":{"name":"fixture","version":"1"}}}\n' "$id"
+            ;;
+        *'"method":"tools/list"'*)
+            printf '{"jsonrpc":"2.0","id":%s,"result":{"tools":[{"name":"echo","description":"Echo the text back","inputSchema":{"type":"object","properties":{"text":{"type":"string"}}}}]}}\n' "$id"
+            ;;
+        *'"method":"tools/call"'*)
+            text=$(printf '%s' "$line" | sed -n 's/.*"text":"\([^"]*\)".*/\1/p')
+            printf '{"jsonrpc":"2.0","id":%s,"result":{"content":[{"type":"text","text":"echo: %s"}]}}\n' "$id" "$text"
+            ;;
+    esac
+done
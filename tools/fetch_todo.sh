@@ -0,0 +1,7 @@
+#!/bin/sh
+# Example external command for prompts/tool_fetch_todo.json
+#
+# The tool arguments arrive as JSON on stdin, and whatever this prints on
+# stdout is sent back to the model as the tool result.
+
+cat "${TODO_FILE:-$HOME/todo.txt}"